@@ -1,22 +1,693 @@
 use anyhow::{Context, Result};
 use rayon::prelude::*;
-use std::{fs, path::Path};
-use tree_sitter::Parser;
+use std::{
+  collections::{HashMap, HashSet},
+  fs,
+  ops::Range,
+  path::{Path, PathBuf},
+  sync::{Arc, LazyLock, Mutex},
+};
+use tree_sitter::{Parser, Query};
 
 use crate::{
-  api::{self, grammar::Grammars, text},
-  config::{FormatterSpecs, LanguageFormatters},
+  api::{
+    self,
+    grammar::{self, Grammars},
+    injections::{InjectedRegion, RegionIndent},
+    overrides::Overrides,
+    text,
+  },
+  config::{
+    DEFAULT_MAX_INJECTION_DEPTH, FormatCondition, FormatOrder, FormatterProtocol, FormatterSpec,
+    FormatterSpecs, LanguageAliases, LanguageFormatSpec, LanguageFormatters, LanguageSpec, OnError,
+    SafetyCheck,
+  },
   wasm::formatter::WasmFormatter,
 };
 
+mod cache;
+mod combined;
+mod daemon;
+mod placeholders;
 mod runner;
+mod safety;
+mod verify;
+mod warnings;
+mod wrap;
+pub use cache::{CacheKey, FormatCache};
+pub use daemon::Daemons;
 pub use runner::FormatOpts;
+pub use warnings::{FormatWarning, FormatWarnings, WarningKind};
 
+#[derive(Clone)]
 pub struct FormatContext<'a> {
   pub grammars: &'a Grammars,
   pub languages: &'a LanguageFormatters,
+  pub aliases: &'a LanguageAliases,
   pub formatters: &'a FormatterSpecs,
   pub wasm_formatter: &'a WasmFormatter,
+  /// The running processes of `protocol = "jsonrpc"` formatters.
+  pub daemons: Arc<Daemons>,
+  pub cache: Arc<FormatCache>,
+  pub overrides: Arc<Overrides>,
+  pub on_error: OnError,
+  pub warnings: Arc<FormatWarnings>,
+  /// The optional formatters already warned about as missing, so that it's only done once per run.
+  pub missing_formatters: Arc<Mutex<HashSet<String>>>,
+  /// Format the result a second time, and fail if it changes.
+  pub verify_idempotent: bool,
+  pub safety_check: SafetyCheck,
+  /// How deeply injected regions may be nested. The root document is at depth 0.
+  pub max_injection_depth: usize,
+  /// How many times the document may be formatted in turn with its injected regions before giving
+  /// up on it converging.
+  pub max_passes: usize,
+  pub format_order: FormatOrder,
+}
+
+/// The print width single line regions are formatted with. It's large enough that formatters have
+/// no reason to break lines, but small enough for those which read it as a signed 32 bit number.
+pub const SINGLE_LINE_PRINT_WIDTH: u32 = 100_000;
+
+static NO_ALIASES: LazyLock<LanguageAliases> = LazyLock::new(LanguageAliases::new);
+
+impl<'a> FormatContext<'a> {
+  /// A context with no aliases, overrides or formatter processes, an empty cache and warnings, and
+  /// the default settings for everything else.
+  pub fn new(
+    grammars: &'a Grammars,
+    languages: &'a LanguageFormatters,
+    formatters: &'a FormatterSpecs,
+    wasm_formatter: &'a WasmFormatter,
+  ) -> Self {
+    Self {
+      grammars,
+      languages,
+      aliases: &NO_ALIASES,
+      formatters,
+      wasm_formatter,
+      daemons: Arc::new(Daemons::new()),
+      cache: Arc::new(FormatCache::new()),
+      overrides: Arc::new(Overrides::default()),
+      on_error: OnError::default(),
+      warnings: Arc::new(FormatWarnings::new()),
+      missing_formatters: Arc::new(Mutex::new(HashSet::new())),
+      verify_idempotent: false,
+      safety_check: SafetyCheck::default(),
+      max_injection_depth: DEFAULT_MAX_INJECTION_DEPTH,
+      max_passes: 1,
+      format_order: FormatOrder::default(),
+    }
+  }
+}
+
+/// Formatted source, along with the warnings for any regions within it which failed to format. Until
+/// they reach the document root, warning lines are 0-based and relative to the start of the source.
+#[derive(Debug, Clone, Default)]
+pub struct Formatted {
+  pub source: Vec<u8>,
+  pub warnings: Vec<FormatWarning>,
+}
+
+// Derive a path for an injected region from the path of its host file. The extension is replaced by
+// the first file type registered by the region's grammar, falling back to the language name.
+fn injection_path(host_path: &Path, language: &str, grammars: &Grammars) -> PathBuf {
+  let extension = grammars
+    .get(language)
+    .and_then(|grammar| grammar.file_types.first())
+    .map(String::as_str)
+    .unwrap_or(language);
+  host_path.with_extension(extension)
+}
+
+/// Where the source being formatted sits within the document being formatted.
+#[derive(Debug, Clone, Copy)]
+struct Scope<'a> {
+  depth: usize,
+  /// The column in the root document at which the source starts.
+  column: usize,
+  parent_language: Option<&'a str>,
+  captures: &'a [String],
+  document_path: Option<&'a Path>,
+  /// The formatter chosen by the injection query, replacing those of the language.
+  formatter: Option<&'a str>,
+  /// The print width chosen by the injection query, as a column in the root document.
+  print_width: Option<u32>,
+  /// Text wrapped around the source while it is formatted, chosen by the injection query.
+  wrap_prefix: &'a str,
+  wrap_suffix: &'a str,
+}
+
+// Path conditions are matched relative to `root`, the same as overrides.
+fn condition_matches(condition: &FormatCondition, scope: &Scope, root: &Path) -> bool {
+  if let Some(parent_language) = &condition.parent_language
+    && scope.parent_language != Some(parent_language.as_str())
+  {
+    return false;
+  }
+
+  if let Some(depth) = &condition.depth
+    && !depth.matches(scope.depth)
+  {
+    return false;
+  }
+
+  if let Some(capture) = &condition.capture
+    && !scope.captures.contains(capture)
+  {
+    return false;
+  }
+
+  if let Some(glob) = &condition.path {
+    let Some(path) = scope.document_path else {
+      return false;
+    };
+    if !glob.is_match(path.strip_prefix(root).unwrap_or(path)) {
+      return false;
+    }
+  }
+
+  true
+}
+
+// Whether any formatter has a condition matching `matches`.
+fn has_conditions(
+  languages: &LanguageFormatters,
+  matches: impl Fn(&FormatCondition) -> bool,
+) -> bool {
+  languages
+    .values()
+    .flat_map(LanguageSpec::formatters)
+    .flat_map(|spec| spec.candidates())
+    .any(|candidate| candidate.condition().is_some_and(&matches))
+}
+
+// Whether any print width is a column in the root document, in which case the result of formatting
+// a region depends on where it starts.
+fn has_absolute_print_widths(format_context: &FormatContext) -> bool {
+  let query_sets_print_width = |query: &Query| {
+    (0..query.pattern_count()).any(|pattern| {
+      query
+        .property_settings(pattern)
+        .iter()
+        .any(|property| &*property.key == "pruner.print-width")
+    })
+  };
+
+  format_context
+    .languages
+    .values()
+    .any(|spec| spec.settings().print_width.is_some())
+    || format_context
+      .grammars
+      .values()
+      .any(|grammar| query_sets_print_width(&grammar.injections))
+}
+
+/// Which parts of the scope of a region are part of its [`CacheKey`]. Those which can't affect how
+/// regions are formatted are left out, so that more regions share results.
+#[derive(Debug, Clone, Copy)]
+struct KeyScope {
+  depth: bool,
+  column: bool,
+  document_path: bool,
+}
+
+impl KeyScope {
+  fn new(format_context: &FormatContext) -> Self {
+    Self {
+      depth: has_conditions(format_context.languages, |condition| {
+        condition.depth.is_some()
+      }),
+      column: has_absolute_print_widths(format_context),
+      // Regions of different files can't share results when formatters depend on where the file is
+      document_path: has_conditions(format_context.languages, |condition| {
+        condition.path.is_some()
+      }) || format_context
+        .formatters
+        .values()
+        .any(FormatterSpec::depends_on_path),
+    }
+  }
+}
+
+fn is_available(formatter_name: &str, format_context: &FormatContext) -> bool {
+  if format_context.wasm_formatter.has_formatter(formatter_name) {
+    return true;
+  }
+
+  format_context
+    .formatters
+    .get(formatter_name)
+    .is_some_and(|formatter| runner::command_exists(&formatter.cmd))
+}
+
+// Pick the first available formatter out of a list of candidates.
+//
+// When none of an explicit list of alternatives are available this is an error, unless every
+// candidate is optional. A lone formatter is run regardless, the same as before alternatives
+// existed, so that a missing one fails to run and unconfigured names, which can refer to plugins
+// only configured in some profiles, leave the source as it is.
+fn select_formatter<'a>(
+  candidates: &[&'a LanguageFormatSpec],
+  alternatives: bool,
+  format_context: &FormatContext,
+) -> Result<Option<&'a str>> {
+  if let Some(candidate) = candidates
+    .iter()
+    .find(|candidate| is_available(candidate.formatter(), format_context))
+  {
+    return Ok(Some(candidate.formatter()));
+  }
+
+  let names = candidates
+    .iter()
+    .map(|candidate| candidate.formatter())
+    .collect::<Vec<_>>()
+    .join(", ");
+
+  match candidates {
+    [] => Ok(None),
+    _ if candidates.iter().all(|candidate| candidate.optional()) => {
+      // Only warn once per run rather than once for every region
+      if format_context
+        .missing_formatters
+        .lock()
+        .unwrap()
+        .insert(names.clone())
+      {
+        log::warn!("No available formatter among: {names}");
+      }
+      Ok(None)
+    }
+    [candidate] if !alternatives => Ok(Some(candidate.formatter())),
+    _ => anyhow::bail!("No available formatter among: {names}"),
+  }
+}
+
+fn run_formatter(
+  formatter_name: &str,
+  source: &[u8],
+  opts: &FormatOpts,
+  format_context: &FormatContext,
+) -> Result<Vec<u8>> {
+  if let Some(formatter) = format_context.formatters.get(formatter_name) {
+    match formatter.protocol {
+      Some(FormatterProtocol::Jsonrpc) => format_context.daemons.format(formatter, source, opts),
+      None => runner::format(formatter, source, opts),
+    }
+    .context(format!("Failed to run formatter: {formatter_name}"))
+  } else if format_context.wasm_formatter.has_formatter(formatter_name) {
+    format_context
+      .wasm_formatter
+      .format(formatter_name, source, opts)
+  } else {
+    Ok(Vec::from(source))
+  }
+}
+
+struct PreparedRegion {
+  region: InjectedRegion,
+  key: CacheKey,
+  escaping: text::Escaping,
+  /// How the escaped characters of the region were spelled, reused when escaping it again.
+  spellings: text::Spellings,
+  indent: String,
+  /// Whether the first line is indented too, as the region starts at the beginning of a line.
+  indent_first_line: bool,
+  trailing_newlines: Vec<u8>,
+  /// The parts of a combined region, empty for regions made of a single node.
+  combined_parts: Vec<combined::CombinedPart>,
+  /// Placeholders masked in `key.source`, restored once it has been formatted.
+  placeholders: placeholders::Placeholders,
+}
+
+impl PreparedRegion {
+  // Mask the placeholder patterns configured for the language of the region in its source.
+  fn mask_placeholders(&mut self, patterns: &[String]) -> Result<()> {
+    if patterns.is_empty() {
+      return Ok(());
+    }
+    let source = std::str::from_utf8(&self.key.source)?;
+    let masked = self
+      .placeholders
+      .mask_patterns(source, &placeholders::compile(patterns)?);
+    self.key.source = masked.into_bytes();
+    Ok(())
+  }
+}
+
+// Extract the source of an injected region from the document, unescaping it and stripping its
+// indentation so it can be handed to the formatters of its language.
+//
+// Placeholders captured by the injection query are masked too, but only in regions made of a single
+// node.
+fn prepare_region(
+  document: &[u8],
+  region: &InjectedRegion,
+  opts: &FormatOpts,
+  format_root: bool,
+  scope: &Scope,
+  key_scope: KeyScope,
+  tab_width: usize,
+) -> Result<PreparedRegion> {
+  let source_slice = &document[region.range.start_byte..region.range.end_byte];
+  let escaping = text::Escaping::new(region.opts.escape_style, &region.opts.escape_chars);
+  let mut placeholders = placeholders::Placeholders::new(std::str::from_utf8(source_slice)?);
+
+  // The indentation of a combined region can only come from its content, as its parts are spread
+  // across the document.
+  let (unescaped_source_str, spellings, combined_parts) = if region.parts.len() > 1 {
+    combined::join(document, region, &escaping)?
+  } else {
+    let placeholder_ranges = region
+      .opts
+      .placeholders
+      .iter()
+      .map(|range| range.start - region.range.start_byte..range.end - region.range.start_byte)
+      .collect::<Vec<_>>();
+    let source_str =
+      placeholders.mask_ranges(std::str::from_utf8(source_slice)?, &placeholder_ranges);
+    let (unescaped_source_str, spellings) = escaping.unescape_spelled(&source_str);
+    (unescaped_source_str, spellings, Vec::new())
+  };
+  let trailing_newlines = if combined_parts.is_empty() {
+    text::trailing_newlines(source_slice)
+  } else {
+    text::trailing_newlines(unescaped_source_str.as_bytes())
+  };
+
+  let mut indent = if combined_parts.is_empty() {
+    text::indent_for_byte(document, region.range.start_byte)
+  } else {
+    String::new()
+  };
+  let mut column = text::column_for_byte(document, region.range.start_byte, tab_width);
+  let mut indent_first_line = false;
+  let mut normalized_source = unescaped_source_str;
+  if !indent.is_empty() {
+    normalized_source = text::strip_leading_indent(&normalized_source, &indent, tab_width);
+  } else {
+    let min_indent = text::min_leading_indent(&normalized_source);
+    if !min_indent.is_empty() {
+      normalized_source = text::strip_leading_indent(&normalized_source, &min_indent, tab_width);
+      column = text::display_width(&min_indent, tab_width);
+      indent = min_indent;
+      indent_first_line = true;
+    }
+  }
+  let line_prefix = region.opts.line_prefix.as_deref().unwrap_or_default();
+  let prefix_width = text::display_width(line_prefix, tab_width);
+  if !line_prefix.is_empty() {
+    normalized_source = text::strip_line_prefix(&normalized_source, line_prefix);
+    column += prefix_width;
+  }
+  placeholders.map_originals(|original| {
+    let original = escaping.unescape(original);
+    let original = text::strip_leading_indent(&original, &indent, tab_width);
+    text::strip_line_prefix(&original, line_prefix)
+  });
+
+  // The indentation stripped above is what the region is reinserted with, unless the query asks
+  // for another layout
+  match region.opts.indent {
+    RegionIndent::Auto => {}
+    RegionIndent::Align => {
+      indent = text::indent_for_byte(document, region.range.start_byte);
+      indent_first_line = false;
+      column = text::display_width(&indent, tab_width) + prefix_width;
+    }
+    RegionIndent::Block(width) => {
+      indent = text::line_indent(document, region.range.start_byte) + &" ".repeat(width);
+      indent_first_line = text::column_for_byte(document, region.range.start_byte, tab_width) == 0;
+      column = text::display_width(&indent, tab_width) + prefix_width;
+    }
+  }
+  // Single line regions are formatted as if lines could be very long, so formatters have no reason
+  // to break them
+  let (adjusted_printwidth, print_width) = if region.opts.single_line {
+    (SINGLE_LINE_PRINT_WIDTH, Some(SINGLE_LINE_PRINT_WIDTH))
+  } else {
+    (
+      opts.printwidth.saturating_sub(column as u32),
+      region.opts.print_width,
+    )
+  };
+
+  Ok(PreparedRegion {
+    region: region.clone(),
+    key: CacheKey {
+      language: region.lang.clone(),
+      printwidth: adjusted_printwidth.max(1),
+      source: normalized_source.into_bytes(),
+      format_root,
+      depth: key_scope.depth.then_some(scope.depth + 1),
+      // Single line regions have a print width of their own too
+      column: (key_scope.column || print_width.is_some()).then_some(scope.column + column),
+      parent_language: opts.language.to_string(),
+      captures: region.opts.captures.clone(),
+      formatter: region.opts.formatter.clone(),
+      print_width,
+      variables: region.opts.variables.clone(),
+      wrap_prefix: region.opts.wrap_prefix.clone(),
+      wrap_suffix: region.opts.wrap_suffix.clone(),
+      document_path: scope
+        .document_path
+        .filter(|_| key_scope.document_path)
+        .map(Path::to_path_buf),
+    },
+    escaping,
+    spellings,
+    indent,
+    indent_first_line,
+    trailing_newlines,
+    combined_parts,
+    placeholders,
+  })
+}
+
+// Fail for regions nested too deeply, or which are the same as the source containing them. The
+// latter would otherwise keep being found again at every level, such as with a self-injecting
+// query.
+fn check_nesting(
+  key: &CacheKey,
+  parent_source: &[u8],
+  opts: &FormatOpts,
+  scope: &Scope,
+  format_context: &FormatContext,
+) -> Result<()> {
+  let depth = scope.depth + 1;
+  if key.language == opts.language && key.source.trim_ascii() == parent_source.trim_ascii() {
+    anyhow::bail!(
+      "Injection cycle, the [{}] region is the same as the document containing it",
+      key.language
+    );
+  }
+  if depth > format_context.max_injection_depth {
+    anyhow::bail!(
+      "The [{}] region is nested {} levels deep, more than the max_injection_depth of {}",
+      key.language,
+      depth,
+      format_context.max_injection_depth
+    );
+  }
+  Ok(())
+}
+
+fn format_region_source(
+  key: &CacheKey,
+  opts: &FormatOpts,
+  scope: &Scope,
+  format_context: &FormatContext,
+) -> Result<Formatted> {
+  if let Some(result) = format_context.cache.get(key) {
+    log::trace!("Using cached result for [{}] region", key.language);
+    return Ok(result);
+  }
+
+  let region_path = opts
+    .path
+    .map(|path| injection_path(path, &key.language, format_context.grammars));
+  let result = format_scoped(
+    &key.source,
+    &FormatOpts {
+      printwidth: key.printwidth,
+      language: &key.language,
+      path: region_path.as_deref(),
+      indent_width: None,
+      variables: &key.variables,
+    },
+    key.format_root,
+    &Scope {
+      depth: scope.depth + 1,
+      // Without a print width relative to it, the column can't affect the result
+      column: key.column.unwrap_or_default(),
+      parent_language: Some(&key.parent_language),
+      captures: &key.captures,
+      document_path: scope.document_path,
+      formatter: key.formatter.as_deref(),
+      print_width: key.print_width,
+      wrap_prefix: key.wrap_prefix.as_deref().unwrap_or_default(),
+      wrap_suffix: key.wrap_suffix.as_deref().unwrap_or_default(),
+    },
+    format_context,
+  )?;
+
+  format_context.cache.insert(key.clone(), result.clone());
+  Ok(result)
+}
+
+// A range of the document and the text replacing it.
+type Replacement = (Range<usize>, Vec<u8>);
+
+// Re-indent a formatted region, escape it with `escaping` and restore its line prefix and the
+// newlines around it. Single line regions are joined onto one line first.
+fn finish_text(
+  prepared: &PreparedRegion,
+  mut formatted: Vec<u8>,
+  escaping: &text::Escaping,
+) -> Result<Vec<u8>> {
+  let indent = &prepared.indent;
+  text::strip_trailing_newlines(&mut formatted);
+  if prepared.region.opts.single_line {
+    formatted = text::join_lines(std::str::from_utf8(&formatted)?).into_bytes();
+  }
+  if !escaping.is_none() {
+    let formatted_str = String::from_utf8(formatted)?;
+    formatted = escaping
+      .escape_spelled(&formatted_str, &mut prepared.spellings.clone())
+      .into_bytes();
+  }
+  if let Some(line_prefix) = &prepared.region.opts.line_prefix {
+    let formatted_str = String::from_utf8(formatted)?;
+    formatted = text::add_line_prefix(&formatted_str, line_prefix).into_bytes();
+  }
+  if let Some(count) = prepared.region.opts.leading_newlines {
+    let start = formatted
+      .iter()
+      .position(|byte| *byte != b'\n' && *byte != b'\r')
+      .unwrap_or(formatted.len());
+    formatted.splice(..start, std::iter::repeat_n(b'\n', count));
+  }
+  match prepared.region.opts.trailing_newlines {
+    Some(count) => formatted.extend(std::iter::repeat_n(b'\n', count)),
+    None => formatted.extend_from_slice(&prepared.trailing_newlines),
+  }
+  if prepared.indent_first_line
+    && !indent.is_empty()
+    && formatted.first() != Some(&b'\n')
+    && formatted.first() != Some(&b'\r')
+  {
+    formatted.splice(0..0, indent.bytes());
+  }
+  text::offset_lines(&mut formatted, indent);
+  Ok(formatted)
+}
+
+// Re-escape and re-indent a formatted region so it can be spliced back into the document. Combined
+// regions are split back into their parts first, returning `None` if that isn't possible.
+fn finish_region(
+  prepared: &PreparedRegion,
+  formatted: Vec<u8>,
+) -> Result<Option<Vec<Replacement>>> {
+  let range = prepared.region.range.start_byte..prepared.region.range.end_byte;
+  if prepared.combined_parts.is_empty() {
+    let formatted = finish_text(prepared, formatted, &prepared.escaping)?;
+    return Ok(Some(vec![(range, formatted)]));
+  }
+
+  let formatted = String::from_utf8(finish_text(
+    prepared,
+    formatted,
+    &text::Escaping::default(),
+  )?)?;
+  let Some(pieces) = combined::split(&formatted, &prepared.combined_parts) else {
+    return Ok(None);
+  };
+  let mut spellings = prepared.spellings.clone();
+  let replacements = pieces
+    .into_iter()
+    .map(|(range, piece)| {
+      let piece = prepared.escaping.escape_spelled(&piece, &mut spellings);
+      (range, piece.into_bytes())
+    })
+    .collect();
+  Ok(Some(replacements))
+}
+
+// Tracks the replacements spliced into a document, so that ranges of the original document can be
+// mapped onto it regardless of the order replacements are made in.
+#[derive(Default, Clone)]
+struct Splices {
+  // The end of each replaced range, and the change in length it caused
+  applied: Vec<(usize, isize)>,
+}
+
+impl Splices {
+  fn shift(&self, byte: usize) -> usize {
+    let delta = self
+      .applied
+      .iter()
+      .filter(|(end, _)| *end <= byte)
+      .map(|(_, delta)| delta)
+      .sum::<isize>();
+    byte.saturating_add_signed(delta)
+  }
+
+  // Splice non-overlapping replacements into `document`, without recording them.
+  fn apply(&self, document: &mut Vec<u8>, replacements: &[Replacement]) {
+    let mut ordered = replacements.iter().collect::<Vec<_>>();
+    ordered.sort_by(|(a, _), (b, _)| b.start.cmp(&a.start));
+    for (range, replacement) in ordered {
+      let range = self.shift(range.start)..self.shift(range.end);
+      document.splice(range, replacement.iter().copied());
+    }
+  }
+
+  fn record(&mut self, replacements: &[Replacement]) {
+    self.applied.extend(
+      replacements
+        .iter()
+        .map(|(range, replacement)| (range.end, replacement.len() as isize - range.len() as isize)),
+    );
+  }
+}
+
+// Splice formatted regions into the document, leaving out those which fail the safety `check`. All
+// the regions are checked at once, and only when that fails are they split in halves to find the
+// ones to blame. Each half is checked with the regions already accepted, so that the document always
+// passes the check.
+fn splice_checked<'r>(
+  document: &mut Vec<u8>,
+  splices: &mut Splices,
+  regions: &'r [(InjectedRegion, Vec<Replacement>)],
+  check: &mut impl FnMut(&[u8]) -> Result<Option<String>>,
+  rejected: &mut Vec<(&'r InjectedRegion, String)>,
+) -> Result<()> {
+  if regions.is_empty() {
+    return Ok(());
+  }
+
+  let mut candidate = document.clone();
+  let mut candidate_splices = splices.clone();
+  for (_, replacements) in regions {
+    candidate_splices.apply(&mut candidate, replacements);
+    candidate_splices.record(replacements);
+  }
+
+  match check(&candidate)? {
+    None => {
+      *document = candidate;
+      *splices = candidate_splices;
+    }
+    Some(problem) if regions.len() == 1 => rejected.push((&regions[0].0, problem)),
+    Some(_) => {
+      let (first, second) = regions.split_at(regions.len() / 2);
+      splice_checked(document, splices, first, check, rejected)?;
+      splice_checked(document, splices, second, check, rejected)?;
+    }
+  }
+  Ok(())
 }
 
 pub fn format(
@@ -26,115 +697,523 @@ pub fn format(
   is_root: bool,
   format_context: &FormatContext,
 ) -> Result<Vec<u8>> {
+  if let Err(err) = std::str::from_utf8(source) {
+    log::warn!(
+      "Skipping {}, it is not valid utf-8: {err}",
+      opts
+        .path
+        .map_or("[stdin]".into(), |path| path.to_string_lossy())
+    );
+    return Ok(Vec::from(source));
+  }
+
+  // Formatters and injected regions only ever see LF line endings, the original style is restored
+  // once everything has been formatted.
+  let encoding = text::Encoding::detect(source);
+  let source = encoding.normalize(source);
+
+  let scope = Scope {
+    depth: if is_root { 0 } else { 1 },
+    column: 0,
+    parent_language: None,
+    captures: &[],
+    document_path: opts.path,
+    formatter: None,
+    print_width: None,
+    wrap_prefix: "",
+    wrap_suffix: "",
+  };
+  let result = format_passes(&source, opts, format_root, &scope, format_context)?;
+
+  format_context
+    .warnings
+    .extend(result.warnings.into_iter().map(|warning| FormatWarning {
+      path: opts.path.map(Path::to_path_buf),
+      line: warning.line + 1,
+      ..warning
+    }));
+
+  let result = encoding.restore(&result.source);
+
+  if format_context.verify_idempotent {
+    let second = format(
+      &result,
+      opts,
+      format_root,
+      is_root,
+      &FormatContext {
+        cache: Arc::new(FormatCache::new()),
+        warnings: Arc::new(FormatWarnings::new()),
+        verify_idempotent: false,
+        ..format_context.clone()
+      },
+    )
+    .context("Failed to format the result a second time")?;
+    if second != result {
+      anyhow::bail!(verify::describe(
+        &result,
+        &second,
+        opts.language,
+        format_context
+      ));
+    }
+  }
+
+  Ok(result)
+}
+
+// Format the document and its injected regions in turn until the result stops changing, as regions
+// which change width can leave the layout of the root wrong.
+fn format_passes(
+  source: &[u8],
+  opts: &FormatOpts,
+  format_root: bool,
+  scope: &Scope,
+  format_context: &FormatContext,
+) -> Result<Formatted> {
+  let mut current = Vec::from(source);
+  // The injections-only pass comes on top of `max_passes`, so that a single pass still runs the
+  // root formatters
+  if format_root && format_context.format_order == FormatOrder::InjectionsFirst {
+    current = format_scoped(&current, opts, false, scope, format_context)?.source;
+  }
+
+  let mut passes = 1;
+  loop {
+    let result = format_scoped(&current, opts, format_root, scope, format_context)?;
+    if result.source == current || passes >= format_context.max_passes {
+      if result.source != current && format_context.max_passes > 1 {
+        log::warn!(
+          "[{}] document still changed after {passes} formatting passes",
+          opts.language
+        );
+      }
+      return Ok(result);
+    }
+    current = result.source;
+    passes += 1;
+  }
+}
+
+// Run the formatters of the source, wrapping it in the prefix and suffix chosen by the injection
+// query while they do.
+fn run_formatters(
+  source: Vec<u8>,
+  language_spec: Option<&LanguageSpec>,
+  opts: &FormatOpts,
+  scope: &Scope,
+  format_context: &FormatContext,
+) -> Result<Vec<u8>> {
+  if scope.wrap_prefix.is_empty() && scope.wrap_suffix.is_empty() {
+    return run_language_formatters(source, language_spec, opts, scope, format_context);
+  }
+
+  let wrapped = wrap::wrap(&source, scope.wrap_prefix, scope.wrap_suffix);
+  let result =
+    run_language_formatters(wrapped.clone(), language_spec, opts, scope, format_context)?;
+  if result == wrapped {
+    return Ok(source);
+  }
+  wrap::unwrap(&result, scope.wrap_prefix, scope.wrap_suffix)
+    .with_context(|| format!("Failed to unwrap the formatted [{}] region", opts.language))
+}
+
+// Run the formatters configured for the language of the source, in order.
+fn run_language_formatters(
+  source: Vec<u8>,
+  language_spec: Option<&LanguageSpec>,
+  opts: &FormatOpts,
+  scope: &Scope,
+  format_context: &FormatContext,
+) -> Result<Vec<u8>> {
+  let mut formatted_result = source;
+  let is_root = scope.depth == 0;
+
+  if let Some(formatter_name) = scope.formatter {
+    if !format_context.formatters.contains_key(formatter_name)
+      && !format_context.wasm_formatter.has_formatter(formatter_name)
+    {
+      anyhow::bail!("Unknown formatter {formatter_name}, set by the injection query");
+    }
+    return run_formatter(formatter_name, &formatted_result, opts, format_context);
+  }
+
+  for format_spec in language_spec
+    .map(LanguageSpec::formatters)
+    .unwrap_or_default()
+  {
+    let mut candidates = Vec::new();
+    for candidate in format_spec.candidates() {
+      let applies = if is_root {
+        candidate.run_in_root()
+      } else {
+        candidate.run_in_injections()
+      };
+      let matches = match candidate.condition() {
+        Some(condition) => condition_matches(condition, scope, format_context.overrides.root()),
+        None => true,
+      };
+      if applies && matches {
+        candidates.push(candidate);
+      }
+    }
+
+    let Some(formatter_name) = select_formatter(
+      &candidates,
+      matches!(format_spec, LanguageFormatSpec::Alternatives(_)),
+      format_context,
+    )?
+    else {
+      continue;
+    };
+
+    let result = run_formatter(formatter_name, &formatted_result, opts, format_context)?;
+    if let Err(err) = std::str::from_utf8(&result) {
+      log::warn!(
+        "Formatter {formatter_name} returned invalid utf-8 for [{}], keeping the original: {err}",
+        opts.language
+      );
+      continue;
+    }
+    formatted_result = result;
+  }
+
+  Ok(formatted_result)
+}
+
+fn format_scoped(
+  source: &[u8],
+  opts: &FormatOpts,
+  format_root: bool,
+  scope: &Scope,
+  format_context: &FormatContext,
+) -> Result<Formatted> {
   let mut parser = Parser::new();
 
+  let language_spec = format_context.languages.get(opts.language);
+  let settings = language_spec
+    .map(LanguageSpec::settings)
+    .unwrap_or_default();
+  let printwidth = match scope.print_width {
+    Some(print_width) => print_width.saturating_sub(scope.column as u32).max(1),
+    None => settings.print_width(opts.printwidth, scope.column),
+  };
+  let opts = &FormatOpts {
+    printwidth,
+    indent_width: settings.indent_width.or(opts.indent_width),
+    ..*opts
+  };
+  let tab_width = settings
+    .tab_width
+    .map_or(text::DEFAULT_TAB_WIDTH, |tab_width| tab_width as usize);
+
+  let mut warnings = Vec::new();
   let mut formatted_result = Vec::from(source);
+  let is_root = scope.depth == 0;
 
   if !is_root || format_root {
-    for format_spec in format_context
-      .languages
-      .get(opts.language)
-      .unwrap_or(&Vec::new())
-    {
-      if (is_root && format_spec.run_in_root()) || (!is_root && format_spec.run_in_injections()) {
-        let formatter_name = format_spec.formatter();
-
-        formatted_result = if let Some(formatter) = format_context.formatters.get(formatter_name) {
-          runner::format(formatter, &formatted_result, opts)
-            .context(format!("Failed to run formatter: {formatter_name}"))?
-        } else if format_context.wasm_formatter.has_formatter(formatter_name) {
-          format_context
-            .wasm_formatter
-            .format(formatter_name, &formatted_result, opts)?
-        } else {
-          formatted_result
-        }
+    match run_formatters(formatted_result, language_spec, opts, scope, format_context) {
+      Ok(result) => formatted_result = result,
+      // Failures within injected regions are handled by the document containing them, which keeps
+      // the original text of the whole region.
+      Err(err) if is_root && format_context.on_error == OnError::Skip => {
+        warnings.push(FormatWarning {
+          kind: WarningKind::Failed,
+          path: None,
+          line: 0,
+          language: opts.language.to_string(),
+          message: format!("{err:#}"),
+        });
+        formatted_result = Vec::from(source);
       }
+      Err(err) => return Err(err),
     }
   }
 
   let Some(grammar) = format_context.grammars.get(opts.language) else {
-    return Ok(formatted_result);
+    return Ok(Formatted {
+      source: formatted_result,
+      warnings,
+    });
   };
 
-  let mut injected_regions =
-    api::injections::extract_language_injections(&mut parser, grammar, &formatted_result)?;
-  // Sort in reverse order. File modifications can therefore be applied from end to start
-  injected_regions.sort_by(|a, b| b.range.start_byte.cmp(&a.range.start_byte));
-
-  let formatted_regions = injected_regions
-    .par_iter()
-    .map(|region| {
-      let source_slice = &formatted_result[region.range.start_byte..region.range.end_byte];
-      let escape_chars = text::sort_escape_chars(&region.opts.escape_chars);
-      let source_str = String::from_utf8(Vec::from(source_slice))?;
-      let unescaped_source_str = if escape_chars.is_empty() {
-        source_str
-      } else {
-        text::unescape_text(&source_str, &escape_chars)
-      };
+  if formatted_result != source
+    && let Some(problem) = safety::check(
+      format_context.safety_check,
+      &mut parser,
+      grammar,
+      source,
+      &formatted_result,
+    )?
+  {
+    warnings.push(FormatWarning {
+      kind: WarningKind::Rejected,
+      path: None,
+      line: 0,
+      language: opts.language.to_string(),
+      message: format!(
+        "Rejected the output of the [{}] formatters, {problem}",
+        opts.language
+      ),
+    });
+    formatted_result = Vec::from(source);
+  }
 
-      let mut indent = text::column_for_byte(&formatted_result, region.range.start_byte);
-      let mut indent_from_content = false;
-      let mut normalized_source = unescaped_source_str;
-      if indent > 0 {
-        normalized_source = text::strip_leading_indent(&normalized_source, indent);
-      } else {
-        let min_indent = text::min_leading_indent(&normalized_source);
-        if min_indent > 0 {
-          normalized_source = text::strip_leading_indent(&normalized_source, min_indent);
-          indent = min_indent;
-          indent_from_content = true;
+  // Regions nested within others are formatted first, one level at a time, so that the regions
+  // containing them are formatted with their results. Regions are found again after each level, as
+  // formatting moves the text around them.
+  let mut level = None;
+  loop {
+    let mut injected_regions = api::injections::extract_language_injections(
+      &mut parser,
+      grammar,
+      &formatted_result,
+      scope.parent_language,
+    )?;
+    // The regions within a skipped region are left as they are too
+    let skipped = injected_regions
+      .iter()
+      .filter(|region| region.opts.skip)
+      .cloned()
+      .collect::<Vec<_>>();
+    injected_regions.retain(|region| {
+      !region.opts.skip && !skipped.iter().any(|skipped| skipped.contains(region))
+    });
+    let current = *level.get_or_insert_with(|| {
+      injected_regions
+        .iter()
+        .map(|region| region.nesting)
+        .max()
+        .unwrap_or_default()
+    });
+    injected_regions.retain(|region| region.nesting == current);
+    for region in &mut injected_regions {
+      region.lang = grammar::resolve_language(
+        &region.lang,
+        format_context.grammars,
+        format_context.languages,
+        format_context.aliases,
+      );
+    }
+    // Sort in reverse order. File modifications can therefore be applied from end to start
+    injected_regions.sort_by(|a, b| b.range.start_byte.cmp(&a.range.start_byte));
+
+    let key_scope = KeyScope::new(format_context);
+    let prepared_regions = injected_regions
+      .iter()
+      .map(|region| {
+        let placeholder_patterns = format_context
+          .languages
+          .get(&region.lang)
+          .map(|spec| spec.settings().placeholders)
+          .unwrap_or_default();
+        let mut prepared = prepare_region(
+          &formatted_result,
+          region,
+          opts,
+          format_root,
+          scope,
+          key_scope,
+          tab_width,
+        )?;
+        prepared.mask_placeholders(&placeholder_patterns)?;
+        Ok(prepared)
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    // Regions with identical contents are only formatted once
+    let unique_keys = prepared_regions
+      .iter()
+      .map(|prepared| &prepared.key)
+      .collect::<HashSet<_>>();
+    let formatted_sources = unique_keys
+      .into_par_iter()
+      .map(|key| {
+        let result = check_nesting(key, &formatted_result, opts, scope, format_context)
+          .and_then(|_| format_region_source(key, opts, scope, format_context));
+        match result {
+          Err(err) if format_context.on_error == OnError::Fail => Err(err),
+          result => Ok((key, result)),
         }
-      }
+      })
+      .collect::<Result<HashMap<_, _>>>()?;
 
-      let unescaped_source = normalized_source.into_bytes();
-      let trailing_newlines = text::trailing_newlines(source_slice);
-      let adjusted_printwidth = opts.printwidth.saturating_sub(indent as u32);
-      let mut formatted_sub_result = format(
-        &unescaped_source,
-        &FormatOpts {
-          printwidth: adjusted_printwidth.max(1),
-          language: &region.lang,
-        },
-        format_root,
-        false,
-        format_context,
-      )?;
-      if !escape_chars.is_empty() {
-        let formatted_str = String::from_utf8(formatted_sub_result)?;
-        formatted_sub_result = text::escape_text(&formatted_str, &escape_chars).into_bytes();
-      }
-      text::strip_trailing_newlines(&mut formatted_sub_result);
-      formatted_sub_result.extend_from_slice(&trailing_newlines);
-      if indent_from_content && indent > 0 {
-        if formatted_sub_result.first() != Some(&b'\n')
-          && formatted_sub_result.first() != Some(&b'\r')
-        {
-          let spaces = vec![b' '; indent];
-          formatted_sub_result.splice(0..0, spaces);
+    let mut region_results = Vec::new();
+    for prepared in &prepared_regions {
+      let row = prepared.region.range.start_point.row;
+      match &formatted_sources[&prepared.key] {
+        Ok(formatted) => {
+          warnings.extend(formatted.warnings.iter().map(|warning| FormatWarning {
+            line: warning.line + row,
+            ..warning.clone()
+          }));
+          let source = match prepared.placeholders.restore(&formatted.source) {
+            Ok(source) => source,
+            Err(err) if format_context.on_error == OnError::Fail => return Err(err),
+            Err(err) => {
+              warnings.push(FormatWarning {
+                kind: WarningKind::Rejected,
+                path: None,
+                line: row,
+                language: prepared.region.lang.clone(),
+                message: format!("{err:#}"),
+              });
+              continue;
+            }
+          };
+          if prepared.region.opts.single_line
+            && let Some(region_grammar) = format_context.grammars.get(&prepared.region.lang)
+            && let Some(problem) = safety::check_joined(
+              &mut parser,
+              region_grammar,
+              &source,
+              text::join_lines(std::str::from_utf8(&source)?).as_bytes(),
+            )?
+          {
+            warnings.push(FormatWarning {
+              kind: WarningKind::Skipped,
+              path: None,
+              line: row,
+              language: prepared.region.lang.clone(),
+              message: format!(
+                "The formatted region can't be joined onto a single line, {problem}"
+              ),
+            });
+            continue;
+          }
+          match finish_region(prepared, source)? {
+            // Newlines can still come from placeholders or line prefixes
+            Some(replacements)
+              if prepared.region.opts.single_line
+                && replacements
+                  .iter()
+                  .any(|(_, replacement)| replacement.trim_ascii_end().contains(&b'\n')) =>
+            {
+              warnings.push(FormatWarning {
+                kind: WarningKind::Skipped,
+                path: None,
+                line: row,
+                language: prepared.region.lang.clone(),
+                message: "The formatted region doesn't fit on a single line".into(),
+              })
+            }
+            Some(replacements) => region_results.push((prepared.region.clone(), replacements)),
+            None => warnings.push(FormatWarning {
+              kind: WarningKind::Skipped,
+              path: None,
+              line: row,
+              language: prepared.region.lang.clone(),
+              message: format!(
+                "The formatted combined region can't be split back into its {} parts",
+                prepared.combined_parts.len()
+              ),
+            }),
+          }
         }
+        // The region is left as it is
+        Err(err) => warnings.push(FormatWarning {
+          kind: WarningKind::Failed,
+          path: None,
+          line: row,
+          language: prepared.region.lang.clone(),
+          message: format!("{err:#}"),
+        }),
       }
-      text::offset_lines(&mut formatted_sub_result, indent);
-      Ok((region.clone(), formatted_sub_result))
-    })
-    .collect::<Vec<Result<(api::injections::InjectedRegion, Vec<u8>)>>>();
+    }
+
+    region_results.sort_by(|(a, _), (b, _)| b.range.start_byte.cmp(&a.range.start_byte));
+
+    let mut splices = Splices::default();
+    if format_context.safety_check == SafetyCheck::Off {
+      for (_, replacements) in &region_results {
+        splices.apply(&mut formatted_result, replacements);
+        splices.record(replacements);
+      }
+    } else {
+      let original = formatted_result.clone();
+      let mut check = |candidate: &[u8]| {
+        safety::check(
+          format_context.safety_check,
+          &mut parser,
+          grammar,
+          &original,
+          candidate,
+        )
+      };
+      let mut rejected = Vec::new();
+      splice_checked(
+        &mut formatted_result,
+        &mut splices,
+        &region_results,
+        &mut check,
+        &mut rejected,
+      )?;
+      warnings.extend(rejected.into_iter().map(|(region, problem)| FormatWarning {
+        kind: WarningKind::Rejected,
+        path: None,
+        line: region.range.start_point.row,
+        language: region.lang.clone(),
+        message: format!(
+          "Rejected the formatted region, in [{}] {problem}",
+          opts.language
+        ),
+      }));
+    }
 
-  let mut region_results = Vec::with_capacity(formatted_regions.len());
-  for result in formatted_regions {
-    region_results.push(result?);
+    let Some(next) = current.checked_sub(1) else {
+      break;
+    };
+    level = Some(next);
   }
 
-  region_results.sort_by(|(a, _), (b, _)| b.range.start_byte.cmp(&a.range.start_byte));
+  warnings.sort_by_key(|warning| warning.line);
+  Ok(Formatted {
+    source: formatted_result,
+    warnings,
+  })
+}
 
-  for (region, formatted_sub_result) in region_results {
-    formatted_result.splice(
-      region.range.start_byte..region.range.end_byte,
-      formatted_sub_result,
-    );
+/// Format the contents of the file at `path`, applying any overrides which match the path. The file
+/// itself is not read.
+pub fn format_document(
+  source: &[u8],
+  path: &Path,
+  opts: &FormatOpts,
+  format_root: bool,
+  format_context: &FormatContext,
+) -> Result<Vec<u8>> {
+  let resolved =
+    format_context
+      .overrides
+      .resolve(path, format_context.languages, format_context.formatters);
+
+  if resolved.skip {
+    log::debug!("Skipping {} due to overrides", path.to_string_lossy());
+    return Ok(Vec::from(source));
   }
 
-  Ok(formatted_result)
+  let context = FormatContext {
+    languages: resolved
+      .languages
+      .as_ref()
+      .unwrap_or(format_context.languages),
+    formatters: resolved
+      .formatters
+      .as_ref()
+      .unwrap_or(format_context.formatters),
+    cache: resolved
+      .cache
+      .clone()
+      .unwrap_or_else(|| format_context.cache.clone()),
+    ..format_context.clone()
+  };
+  let opts = FormatOpts {
+    printwidth: resolved.print_width.unwrap_or(opts.printwidth),
+    path: Some(path),
+    ..*opts
+  };
+
+  format(source, &opts, format_root, true, &context)
 }
 
 pub fn format_file(
@@ -146,7 +1225,7 @@ pub fn format_file(
 ) -> Result<bool> {
   let content = fs::read(file).context("Failed to read temp file after formatting")?;
 
-  let result = format(&content, opts, !skip_root, true, format_context)
+  let result = format_document(&content, file, opts, !skip_root, format_context)
     .context("Failed to format file contents")?;
 
   if result == content {
@@ -181,7 +1260,7 @@ pub fn format_files(
   let exclude_matcher = exclude_glob_builder.build()?;
 
   let walker = ignore::WalkBuilder::new(dir).current_dir(dir).build();
-  walker
+  let formatted = walker
     .filter_map(|entry| entry.ok())
     .filter(|entry| !entry.path().is_dir())
     .filter(|entry| {
@@ -205,5 +1284,8 @@ pub fn format_files(
         Ok(false) => None,
       },
     )
-    .collect::<Result<Vec<String>>>()
+    .collect::<Result<Vec<String>>>();
+
+  format_context.daemons.shutdown();
+  formatted
 }
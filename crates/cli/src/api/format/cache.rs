@@ -0,0 +1,50 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use super::Formatted;
+
+/// Identifies an injected region by everything which can affect how it is formatted.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+  pub language: String,
+  pub printwidth: u32,
+  pub source: Vec<u8>,
+  pub format_root: bool,
+
+  /// How many injections deep the region is. Only set when formatters are conditional on it.
+  pub depth: Option<usize>,
+  /// The column in the root document at which the region starts. Only set when print widths are
+  /// relative to it, from the settings of a language or the injection queries.
+  pub column: Option<usize>,
+  pub parent_language: String,
+  pub captures: Vec<String>,
+  /// Settings from the injection query, see [`crate::api::injections::InjectionOpts`].
+  pub formatter: Option<String>,
+  pub print_width: Option<u32>,
+  pub variables: Vec<(String, String)>,
+  pub wrap_prefix: Option<String>,
+  pub wrap_suffix: Option<String>,
+  /// Only set when formatters are conditional on the path of the file being formatted, or can read
+  /// config files next to it.
+  pub document_path: Option<PathBuf>,
+}
+
+/// Memoizes the results of formatting injected regions for the duration of a run, so that regions
+/// with identical contents are only formatted once. Safe to share between threads.
+#[derive(Debug, Default)]
+pub struct FormatCache {
+  entries: Mutex<HashMap<CacheKey, Formatted>>,
+}
+
+impl FormatCache {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn get(&self, key: &CacheKey) -> Option<Formatted> {
+    self.entries.lock().unwrap().get(key).cloned()
+  }
+
+  pub fn insert(&self, key: CacheKey, result: Formatted) {
+    self.entries.lock().unwrap().insert(key, result);
+  }
+}
@@ -0,0 +1,113 @@
+use anyhow::Result;
+use std::ops::Range;
+
+use crate::api::{injections::InjectedRegion, text};
+
+/// One of the nodes making up a combined region, see `injection.combined`.
+#[derive(Debug, Clone)]
+pub struct CombinedPart {
+  range: Range<usize>,
+  leading_whitespace: String,
+  trailing_whitespace: String,
+  multiline: bool,
+  /// The number of non-whitespace characters in the part.
+  content_chars: usize,
+}
+
+fn content_chars(text: &str) -> usize {
+  text.chars().filter(|char| !char.is_whitespace()).count()
+}
+
+// The length in bytes of the start of `text` holding `count` non-whitespace characters.
+fn content_len(text: &str, count: usize) -> usize {
+  if count == 0 {
+    return 0;
+  }
+
+  let mut seen = 0;
+  for (index, char) in text.char_indices() {
+    if !char.is_whitespace() {
+      seen += 1;
+      if seen == count {
+        return index + char.len_utf8();
+      }
+    }
+  }
+  text.len()
+}
+
+/// Join the unescaped text of the parts of a combined region into a single document. Parts which
+/// start on a later line than the previous part ended are separated by a newline, others are joined
+/// directly. The spellings of the escaped characters of each part are returned in order.
+pub fn join(
+  document: &[u8],
+  region: &InjectedRegion,
+  escaping: &text::Escaping,
+) -> Result<(String, text::Spellings, Vec<CombinedPart>)> {
+  let mut joined = String::new();
+  let mut spellings = text::Spellings::default();
+  let mut parts = Vec::with_capacity(region.parts.len());
+  let mut previous_end_row = None;
+
+  for range in &region.parts {
+    let source = std::str::from_utf8(&document[range.start_byte..range.end_byte])?;
+    let (part_text, part_spellings) = escaping.unescape_spelled(source);
+    spellings.extend(part_spellings);
+
+    if previous_end_row.is_some_and(|row| range.start_point.row > row) && !joined.ends_with('\n') {
+      joined.push('\n');
+    }
+    previous_end_row = Some(range.end_point.row);
+
+    let trimmed_start = part_text.trim_start();
+    parts.push(CombinedPart {
+      range: range.start_byte..range.end_byte,
+      leading_whitespace: part_text[..part_text.len() - trimmed_start.len()].to_string(),
+      trailing_whitespace: trimmed_start[trimmed_start.trim_end().len()..].to_string(),
+      multiline: part_text.trim().contains('\n'),
+      content_chars: content_chars(&part_text),
+    });
+    joined.push_str(&part_text);
+  }
+
+  Ok((joined, spellings, parts))
+}
+
+/// Split a formatted combined region back into its parts, returning the new text of each part.
+///
+/// The formatted text is divided so that each part keeps as many non-whitespace characters as it
+/// had before. The whitespace at the edges of each part is kept, unless the formatter broke the line
+/// before a part, in which case the indentation it chose is used. Returns `None` when the text
+/// can't be divided, because the formatter changed the number of non-whitespace characters or moved
+/// a line break into a part which can't hold one.
+pub fn split(formatted: &str, parts: &[CombinedPart]) -> Option<Vec<(Range<usize>, String)>> {
+  let expected = parts.iter().map(|part| part.content_chars).sum::<usize>();
+  if content_chars(formatted) != expected {
+    return None;
+  }
+
+  let mut pieces = Vec::with_capacity(parts.len());
+  let mut rest = formatted;
+  for (index, part) in parts.iter().enumerate() {
+    let trimmed = rest.trim_start();
+    let whitespace = &rest[..rest.len() - trimmed.len()];
+    let leading = match whitespace.rfind('\n') {
+      Some(newline) => &whitespace[newline + 1..],
+      None if index == 0 => whitespace,
+      None => &part.leading_whitespace,
+    };
+
+    let content = &trimmed[..content_len(trimmed, part.content_chars)];
+    if !part.multiline && content.contains('\n') {
+      return None;
+    }
+
+    pieces.push((
+      part.range.clone(),
+      format!("{leading}{content}{}", part.trailing_whitespace),
+    ));
+    rest = &trimmed[content.len()..];
+  }
+
+  Some(pieces)
+}
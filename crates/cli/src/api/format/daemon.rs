@@ -0,0 +1,372 @@
+use anyhow::{Context, Result};
+use std::{
+  collections::HashMap,
+  io::{BufRead, BufReader, Write},
+  path::Path,
+  process::{Child, ChildStdin, Command, Stdio},
+  sync::{
+    Arc, Mutex,
+    mpsc::{self, Receiver, RecvTimeoutError},
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+use super::{FormatOpts, runner::DEFAULT_INDENT_WIDTH};
+use crate::config::FormatterSpec;
+
+#[derive(serde::Serialize)]
+struct Request<'a> {
+  jsonrpc: &'static str,
+  id: u64,
+  method: &'static str,
+  params: FormatParams<'a>,
+}
+
+#[derive(serde::Serialize)]
+struct FormatParams<'a> {
+  source: &'a str,
+  language: &'a str,
+  print_width: u32,
+  indent_width: u32,
+  path: Option<&'a Path>,
+  variables: HashMap<&'a str, &'a str>,
+}
+
+#[derive(serde::Deserialize)]
+struct Response {
+  id: Option<u64>,
+  result: Option<FormatResult>,
+  error: Option<ResponseError>,
+}
+
+#[derive(serde::Deserialize)]
+struct FormatResult {
+  source: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ResponseError {
+  code: i64,
+  message: String,
+}
+
+// How long a formatter process may take to answer a request before it's given up on.
+const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+// How long a formatter process is given to exit once its stdin is closed.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+// How much of the most recent stderr output of a formatter process is kept for error messages.
+const STDERR_LIMIT: usize = 4096;
+
+struct Daemon {
+  cmd: String,
+  child: Child,
+  stdin: Option<ChildStdin>,
+  messages: Receiver<Result<Vec<u8>>>,
+  stderr: Arc<Mutex<String>>,
+  stderr_closed: Receiver<()>,
+  next_id: u64,
+}
+
+fn read_message(stdout: &mut impl BufRead) -> Result<Vec<u8>> {
+  let mut content_length = None;
+  loop {
+    let mut header = String::new();
+    if stdout.read_line(&mut header)? == 0 {
+      anyhow::bail!("Formatter process closed stdout");
+    }
+
+    let header = header.trim_end();
+    if header.is_empty() {
+      break;
+    }
+
+    if let Some((name, value)) = header.split_once(':')
+      && name.eq_ignore_ascii_case("content-length")
+    {
+      content_length = Some(value.trim().parse::<usize>()?);
+    }
+  }
+
+  let Some(content_length) = content_length else {
+    anyhow::bail!("Formatter response is missing a Content-Length header");
+  };
+
+  let mut body = vec![0; content_length];
+  stdout.read_exact(&mut body)?;
+  Ok(body)
+}
+
+impl Daemon {
+  fn spawn(formatter: &FormatterSpec) -> Result<Self> {
+    log::debug!("Starting formatter process [{}]", formatter.cmd);
+
+    let mut child = Command::new(&formatter.cmd)
+      .args(&formatter.args)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .with_context(|| format!("Failed to start formatter process {}", formatter.cmd))?;
+
+    let stdin = child
+      .stdin
+      .take()
+      .ok_or_else(|| anyhow::anyhow!("Failed to open stdin"))?;
+    let stdout = child
+      .stdout
+      .take()
+      .ok_or_else(|| anyhow::anyhow!("Failed to open stdout"))?;
+    let stderr = child
+      .stderr
+      .take()
+      .ok_or_else(|| anyhow::anyhow!("Failed to open stderr"))?;
+
+    // Responses are read on their own thread, so that waiting for one can time out
+    let (message_sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+      let mut stdout = BufReader::new(stdout);
+      loop {
+        let message = read_message(&mut stdout);
+        let closed = message.is_err();
+        if message_sender.send(message).is_err() || closed {
+          break;
+        }
+      }
+    });
+
+    let stderr_output = Arc::new(Mutex::new(String::new()));
+    let (stderr_sender, stderr_closed) = mpsc::channel();
+    let cmd = formatter.cmd.clone();
+    let output = stderr_output.clone();
+    thread::spawn(move || {
+      for line in BufReader::new(stderr).lines() {
+        let Ok(line) = line else {
+          break;
+        };
+        log::debug!("[{cmd}] {line}");
+        let mut output = output.lock().unwrap();
+        output.push_str(&line);
+        output.push('\n');
+        if output.len() > STDERR_LIMIT {
+          let mut start = output.len() - STDERR_LIMIT;
+          while !output.is_char_boundary(start) {
+            start += 1;
+          }
+          output.drain(..start);
+        }
+      }
+      let _ = stderr_sender.send(());
+    });
+
+    Ok(Self {
+      cmd: formatter.cmd.clone(),
+      child,
+      stdin: Some(stdin),
+      messages,
+      stderr: stderr_output,
+      stderr_closed,
+      next_id: 0,
+    })
+  }
+
+  fn write_message(&mut self, body: &[u8]) -> Result<()> {
+    let stdin = self
+      .stdin
+      .as_mut()
+      .ok_or_else(|| anyhow::anyhow!("Formatter process stdin is closed"))?;
+    write!(stdin, "Content-Length: {}\r\n\r\n", body.len())?;
+    stdin.write_all(body)?;
+    stdin.flush()?;
+    Ok(())
+  }
+
+  fn read_response(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+    match self.messages.recv_timeout(timeout) {
+      Ok(message) => message,
+      Err(RecvTimeoutError::Timeout) => anyhow::bail!(
+        "Formatter process didn't respond within {}s",
+        timeout.as_secs_f64()
+      ),
+      Err(RecvTimeoutError::Disconnected) => anyhow::bail!("Formatter process closed stdout"),
+    }
+  }
+
+  fn request(&mut self, source: &str, opts: &FormatOpts, timeout: Duration) -> Result<Response> {
+    self.next_id += 1;
+    let id = self.next_id;
+
+    let request = serde_json::to_vec(&Request {
+      jsonrpc: "2.0",
+      id,
+      method: "format",
+      params: FormatParams {
+        source,
+        language: opts.language,
+        print_width: opts.printwidth,
+        indent_width: opts.indent_width.unwrap_or(DEFAULT_INDENT_WIDTH),
+        path: opts.path,
+        variables: opts
+          .variables
+          .iter()
+          .map(|(name, value)| (name.as_str(), value.as_str()))
+          .collect(),
+      },
+    })?;
+    self.write_message(&request)?;
+
+    let response: Response = serde_json::from_slice(&self.read_response(timeout)?)
+      .context("Failed to parse formatter response")?;
+    if response.id != Some(id) {
+      anyhow::bail!("Formatter responded with an unexpected request id");
+    }
+
+    Ok(response)
+  }
+
+  // Stop the process, and add whatever it wrote to stderr to `err`.
+  fn fail(mut self, err: anyhow::Error) -> anyhow::Error {
+    self.stop();
+    let _ = self.stderr_closed.recv_timeout(SHUTDOWN_TIMEOUT);
+    let stderr = self.stderr.lock().unwrap();
+    let stderr = stderr.trim_end();
+    if stderr.is_empty() {
+      err
+    } else {
+      err.context(format!(
+        "Formatter process {} wrote to stderr:\n{stderr}",
+        self.cmd
+      ))
+    }
+  }
+
+  // Close stdin so that the process can exit by itself, and kill it if it doesn't in time.
+  fn stop(&mut self) {
+    drop(self.stdin.take());
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while Instant::now() < deadline {
+      match self.child.try_wait() {
+        Ok(Some(_)) | Err(_) => return,
+        Ok(None) => thread::sleep(Duration::from_millis(10)),
+      }
+    }
+    log::debug!("Killing formatter process [{}]", self.cmd);
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
+
+impl Drop for Daemon {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+type DaemonKey = (String, Vec<String>);
+
+/// The long-lived processes of `protocol = "jsonrpc"` formatters. A process serves one request at
+/// a time, so it's taken out of the pool for each request and put back once answered. Processes are
+/// stopped by [`Daemons::shutdown`], or when the pool is dropped.
+pub struct Daemons {
+  idle: Mutex<HashMap<DaemonKey, Vec<Daemon>>>,
+  timeout: Duration,
+}
+
+impl Default for Daemons {
+  fn default() -> Self {
+    Self::with_timeout(DEFAULT_RESPONSE_TIMEOUT)
+  }
+}
+
+impl Daemons {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// A pool whose processes fail requests they take longer than `timeout` to answer.
+  pub fn with_timeout(timeout: Duration) -> Self {
+    Self {
+      idle: Mutex::new(HashMap::new()),
+      timeout,
+    }
+  }
+
+  /// Stop every formatter process, waiting for them to exit.
+  pub fn shutdown(&self) {
+    let daemons = std::mem::take(&mut *self.idle.lock().unwrap());
+    drop(daemons);
+  }
+
+  pub fn format(
+    &self,
+    formatter: &FormatterSpec,
+    source: &[u8],
+    opts: &FormatOpts,
+  ) -> Result<Vec<u8>> {
+    log::trace!(
+      "Sending format request to [{}] with opts {:?}",
+      formatter.cmd,
+      opts
+    );
+
+    let source = std::str::from_utf8(source).context("Formatter input is not valid utf-8")?;
+    let start = Instant::now();
+
+    let key = (formatter.cmd.clone(), formatter.args.clone());
+    let idle = self.idle.lock().unwrap().get_mut(&key).and_then(Vec::pop);
+    let mut daemon = match idle {
+      Some(daemon) => daemon,
+      None => Daemon::spawn(formatter)?,
+    };
+
+    // A failed exchange leaves the stream in an unknown state, so the process is discarded and a
+    // new one started on the next request.
+    let response = match daemon.request(source, opts, self.timeout) {
+      Ok(response) => {
+        self
+          .idle
+          .lock()
+          .unwrap()
+          .entry(key)
+          .or_default()
+          .push(daemon);
+        response
+      }
+      Err(err) => return Err(daemon.fail(err)),
+    };
+
+    log::debug!(
+      "Formatted using [{}] in: {:?}",
+      formatter.cmd,
+      Instant::now().duration_since(start)
+    );
+
+    if let Some(error) = response.error {
+      anyhow::bail!(
+        "Failed to run formatter {} ({}): {}",
+        formatter.cmd,
+        error.code,
+        error.message
+      );
+    }
+
+    let Some(result) = response.result else {
+      anyhow::bail!("Formatter response is missing a result");
+    };
+
+    if result.source.is_empty() {
+      anyhow::bail!(
+        "Unexpected empty result received from command: {}",
+        formatter.cmd
+      );
+    }
+
+    Ok(result.source.into_bytes())
+  }
+}
+
+impl Drop for Daemons {
+  fn drop(&mut self) {
+    self.shutdown();
+  }
+}
@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::ops::Range;
+
+/// Template placeholders such as `${table}` or `:name`, replaced with plain identifiers while a region
+/// is formatted so that formatters accept it, and restored afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct Placeholders {
+  prefix: String,
+  /// The original text of each placeholder, indexed by the number in its identifier.
+  originals: Vec<String>,
+}
+
+pub fn compile(patterns: &[String]) -> Result<Vec<Regex>> {
+  patterns
+    .iter()
+    .map(|pattern| {
+      Regex::new(pattern).with_context(|| format!("Invalid placeholder pattern {pattern:?}"))
+    })
+    .collect()
+}
+
+impl Placeholders {
+  /// Placeholders for the given region source, using identifiers which don't already occur in it.
+  pub fn new(source: &str) -> Self {
+    let mut prefix = String::from("__pruner_");
+    while source.contains(&prefix) {
+      prefix.push('_');
+    }
+    Self {
+      prefix,
+      originals: Vec::new(),
+    }
+  }
+
+  fn identifier(&self, index: usize) -> String {
+    format!("{}{index}__", self.prefix)
+  }
+
+  // Replace the given sorted byte ranges of `text`, skipping any which overlap an earlier one.
+  fn mask(&mut self, text: &str, ranges: impl IntoIterator<Item = Range<usize>>) -> String {
+    let mut masked = String::with_capacity(text.len());
+    let mut end = 0;
+    for range in ranges {
+      if range.start < end || range.is_empty() {
+        continue;
+      }
+      masked.push_str(&text[end..range.start]);
+      masked.push_str(&self.identifier(self.originals.len()));
+      self.originals.push(text[range.clone()].to_string());
+      end = range.end;
+    }
+    masked.push_str(&text[end..]);
+    masked
+  }
+
+  /// Mask the byte ranges of `text` captured as placeholders by the injection query.
+  pub fn mask_ranges(&mut self, text: &str, ranges: &[Range<usize>]) -> String {
+    self.mask(text, ranges.iter().cloned())
+  }
+
+  /// Mask every match of the placeholder patterns of the region's language. When matches overlap
+  /// the one starting first wins, or that of the earlier pattern when they start together.
+  pub fn mask_patterns(&mut self, text: &str, patterns: &[Regex]) -> String {
+    let mut matches = patterns
+      .iter()
+      .flat_map(|pattern| pattern.find_iter(text).map(|found| found.range()))
+      .collect::<Vec<_>>();
+    matches.sort_by_key(|range| range.start);
+    self.mask(text, matches)
+  }
+
+  /// Rewrite the original text of the placeholders masked so far, as the region's source is
+  /// unescaped and dedented after query placeholders are masked.
+  pub fn map_originals(&mut self, f: impl Fn(&str) -> String) {
+    for original in &mut self.originals {
+      *original = f(original);
+    }
+  }
+
+  /// Put the original placeholders back into formatted output, failing if the formatter removed
+  /// any of them.
+  pub fn restore(&self, formatted: &[u8]) -> Result<Vec<u8>> {
+    if self.originals.is_empty() {
+      return Ok(Vec::from(formatted));
+    }
+
+    let mut restored = String::from_utf8(Vec::from(formatted))?;
+    for (index, original) in self.originals.iter().enumerate() {
+      let identifier = self.identifier(index);
+      if !restored.contains(&identifier) {
+        anyhow::bail!("The formatter removed the placeholder {original:?}");
+      }
+      restored = restored.replace(&identifier, original);
+    }
+    Ok(restored.into_bytes())
+  }
+}
@@ -1,28 +1,130 @@
 use anyhow::{Context, Result};
 use std::{
+  collections::HashMap,
   fs,
-  io::Write,
-  path::PathBuf,
+  io::{ErrorKind, Write},
+  path::{Path, PathBuf},
   process::{Command, Stdio},
-  time::{Instant, SystemTime, UNIX_EPOCH},
+  sync::{
+    LazyLock, Mutex,
+    atomic::{AtomicUsize, Ordering},
+  },
+  time::Instant,
 };
 
-use crate::config::FormatterSpec;
+use crate::config::{FormatterSpec, TempFileLocation, TempFileSpec};
 
 #[derive(Debug)]
 pub struct FormatOpts<'a> {
   pub printwidth: u32,
   pub language: &'a str,
+  /// The path of the file the source belongs to. For injected regions this is the path of the host
+  /// file with its extension swapped for one matching the injected language.
+  pub path: Option<&'a Path>,
+  /// The width of one level of indentation. Formatters fall back to [`DEFAULT_INDENT_WIDTH`] when
+  /// this isn't set.
+  pub indent_width: Option<u32>,
+  /// Values for the `$<name>` variables of formatter arguments, set by `@pruner.<name>` captures in
+  /// injection queries.
+  pub variables: &'a [(String, String)],
 }
 
-fn unique_temp_file() -> std::io::Result<PathBuf> {
-  let mut path = std::env::temp_dir();
-  let nanos = SystemTime::now()
-    .duration_since(UNIX_EPOCH)
+pub const DEFAULT_INDENT_WIDTH: u32 = 2;
+
+static COMMANDS_FOUND: LazyLock<Mutex<HashMap<String, bool>>> =
+  LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Check whether a command can be found, either as a path or by searching `PATH`. Results are
+/// remembered for the rest of the run, as this is checked for every formatted region.
+pub fn command_exists(cmd: &str) -> bool {
+  if let Some(found) = COMMANDS_FOUND.lock().unwrap().get(cmd) {
+    return *found;
+  }
+  let found = find_command(cmd);
+  COMMANDS_FOUND
+    .lock()
     .unwrap()
-    .as_nanos();
-  path.push(format!("prune-format-{}-{nanos}", std::process::id()));
-  Ok(path)
+    .insert(cmd.to_string(), found);
+  found
+}
+
+fn find_command(cmd: &str) -> bool {
+  let path = Path::new(cmd);
+  if path.components().count() > 1 {
+    return path.is_file();
+  }
+
+  std::env::var_os("PATH")
+    .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(cmd).is_file()))
+}
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn temp_file_dir(spec: Option<&TempFileSpec>, path: Option<&Path>) -> PathBuf {
+  match spec {
+    Some(TempFileSpec::Sandbox { dir }) => dir.clone(),
+    Some(TempFileSpec::Location(TempFileLocation::Adjacent)) => path
+      .and_then(Path::parent)
+      .map(|parent| {
+        if parent.as_os_str().is_empty() {
+          PathBuf::from(".")
+        } else {
+          parent.to_path_buf()
+        }
+      })
+      .unwrap_or_else(std::env::temp_dir),
+    Some(TempFileSpec::Location(TempFileLocation::System)) | None => std::env::temp_dir(),
+  }
+}
+
+// Create a temp file named after the file being formatted, keeping its extension, so that formatters
+// which infer the parser from the extension or look up config relative to the file behave the same
+// as when called on the real file.
+//
+// Files are named like `.<name>.pruner-<n>.<ext>` and are created exclusively, retrying with the
+// next counter value if the name is already taken.
+fn create_temp_file(
+  spec: Option<&TempFileSpec>,
+  path: Option<&Path>,
+  source: &[u8],
+) -> std::io::Result<PathBuf> {
+  let dir = temp_file_dir(spec, path);
+  fs::create_dir_all(&dir)?;
+
+  let stem = path
+    .and_then(Path::file_stem)
+    .map(|stem| stem.to_string_lossy());
+  let extension = path
+    .and_then(Path::extension)
+    .map(|extension| extension.to_string_lossy());
+
+  loop {
+    let count = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mut name = String::from(".");
+    if let Some(stem) = &stem {
+      name.push_str(stem);
+      name.push('.');
+    }
+    name.push_str(&format!("pruner-{count}"));
+    if let Some(extension) = &extension {
+      name.push('.');
+      name.push_str(extension);
+    }
+
+    let candidate = dir.join(name);
+    match fs::OpenOptions::new()
+      .write(true)
+      .create_new(true)
+      .open(&candidate)
+    {
+      Ok(mut file) => {
+        file.write_all(source)?;
+        return Ok(candidate);
+      }
+      Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+      Err(err) => return Err(err),
+    }
+  }
 }
 
 pub fn format(formatter: &FormatterSpec, source: &[u8], opts: &FormatOpts) -> Result<Vec<u8>> {
@@ -32,8 +134,8 @@ pub fn format(formatter: &FormatterSpec, source: &[u8], opts: &FormatOpts) -> Re
   let mut temp_file: Option<PathBuf> = None;
 
   if !use_stdin {
-    let path = unique_temp_file().context("Failed to create temp file for fomatting")?;
-    fs::write(&path, source).context("Failed to write to temp file")?;
+    let path = create_temp_file(formatter.temp_file.as_ref(), opts.path, source)
+      .context("Failed to create temp file for fomatting")?;
     temp_file = Some(path);
   }
 
@@ -42,11 +144,22 @@ pub fn format(formatter: &FormatterSpec, source: &[u8], opts: &FormatOpts) -> Re
     .map(|path| path.to_string_lossy().to_string())
     .unwrap_or_default();
 
+  // Longer names first, so that `$name` doesn't replace the start of `$names`
+  let mut variables = opts.variables.iter().collect::<Vec<_>>();
+  variables.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+
   let args = formatter.args.iter().map(|arg| {
-    arg
+    let arg = arg
       .replace("$textwidth", &format!("{}", opts.printwidth))
+      .replace(
+        "$indentwidth",
+        &format!("{}", opts.indent_width.unwrap_or(DEFAULT_INDENT_WIDTH)),
+      )
       .replace("$language", opts.language)
-      .replace("$file", &file_var)
+      .replace("$file", &file_var);
+    variables.iter().fold(arg, |arg, (name, value)| {
+      arg.replace(&format!("${name}"), value)
+    })
   });
 
   let mut command = Command::new(&formatter.cmd);
@@ -89,10 +202,8 @@ pub fn format(formatter: &FormatterSpec, source: &[u8], opts: &FormatOpts) -> Re
 
     let mut result = output.stdout;
 
-    if !use_stdin {
-      if let Some(path) = temp_file.as_ref() {
-        result = fs::read(path).context("Failed to read temp file after formatting")?;
-      }
+    if !use_stdin && let Some(path) = temp_file.as_ref() {
+      result = fs::read(path).context("Failed to read temp file after formatting")?;
     }
 
     Ok(result)
@@ -104,10 +215,10 @@ pub fn format(formatter: &FormatterSpec, source: &[u8], opts: &FormatOpts) -> Re
     Instant::now().duration_since(start)
   );
 
-  if let Some(ref path) = temp_file {
-    if let Err(err) = fs::remove_file(path) {
-      log::warn!("Failed to remove temp file {path:?}: {err}");
-    }
+  if let Some(ref path) = temp_file
+    && let Err(err) = fs::remove_file(path)
+  {
+    log::warn!("Failed to remove temp file {path:?}: {err}");
   }
 
   match result {
@@ -0,0 +1,129 @@
+use anyhow::Result;
+use tree_sitter::{Node, Parser, Tree};
+
+use crate::{
+  api::{grammar::Grammar, injections},
+  config::SafetyCheck,
+};
+
+fn parse(parser: &mut Parser, grammar: &Grammar, source: &[u8]) -> Result<Tree> {
+  parser.set_language(&grammar.lang)?;
+  parser
+    .parse(source, None)
+    .ok_or_else(|| anyhow::anyhow!("Parse returned None"))
+}
+
+fn walk<'a>(node: Node<'a>, visit: &mut impl FnMut(Node<'a>)) {
+  visit(node);
+  let mut cursor = node.walk();
+  for child in node.children(&mut cursor) {
+    walk(child, visit);
+  }
+}
+
+fn error_count(tree: &Tree) -> usize {
+  let mut count = 0;
+  walk(tree.root_node(), &mut |node| {
+    if node.is_error() || node.is_missing() {
+      count += 1;
+    }
+  });
+  count
+}
+
+// The kinds of all nodes in the tree, along with the text of each token with whitespace removed
+// unless `keep_whitespace` is set. The text of tokens within injected regions is left out, as
+// formatting those is the whole point.
+fn signature(
+  parser: &mut Parser,
+  grammar: &Grammar,
+  tree: &Tree,
+  source: &[u8],
+  keep_whitespace: bool,
+) -> Result<Vec<(u16, Option<String>)>> {
+  let regions = injections::extract_language_injections(parser, grammar, source, None)?;
+
+  let mut signature = Vec::new();
+  walk(tree.root_node(), &mut |node| {
+    let text = (node.child_count() == 0)
+      .then(|| node.byte_range())
+      .filter(|range| {
+        !regions
+          .iter()
+          .flat_map(|region| &region.parts)
+          .any(|part| range.start < part.end_byte && part.start_byte < range.end)
+      })
+      .map(|range| {
+        let text = String::from_utf8_lossy(&source[range]);
+        if keep_whitespace {
+          text.into_owned()
+        } else {
+          text.split_whitespace().collect()
+        }
+      });
+    signature.push((node.kind_id(), text));
+  });
+  Ok(signature)
+}
+
+/// Check whether `after` can safely replace `before`, both being documents of the language of
+/// `grammar`. Returns a description of the problem when it can't.
+pub fn check(
+  mode: SafetyCheck,
+  parser: &mut Parser,
+  grammar: &Grammar,
+  before: &[u8],
+  after: &[u8],
+) -> Result<Option<String>> {
+  if mode == SafetyCheck::Off {
+    return Ok(None);
+  }
+
+  let before_tree = parse(parser, grammar, before)?;
+  let after_tree = parse(parser, grammar, after)?;
+
+  let before_errors = error_count(&before_tree);
+  let after_errors = error_count(&after_tree);
+  if after_errors > before_errors {
+    return Ok(Some(format!(
+      "the result has {after_errors} syntax errors where the input had {before_errors}"
+    )));
+  }
+
+  if mode == SafetyCheck::Strict
+    && signature(parser, grammar, &before_tree, before, false)?
+      != signature(parser, grammar, &after_tree, after, false)?
+  {
+    return Ok(Some(String::from(
+      "the syntax tree of the result differs from the input",
+    )));
+  }
+
+  Ok(None)
+}
+
+/// Check whether `joined`, the lines of the formatted region `before` joined onto one, still has the
+/// same syntax tree and tokens. Joining breaks regions where a line break ends a comment or is part
+/// of a string. Returns a description of the problem when it does.
+pub fn check_joined(
+  parser: &mut Parser,
+  grammar: &Grammar,
+  before: &[u8],
+  joined: &[u8],
+) -> Result<Option<String>> {
+  if let Some(problem) = check(SafetyCheck::Syntax, parser, grammar, before, joined)? {
+    return Ok(Some(problem));
+  }
+
+  let before_tree = parse(parser, grammar, before)?;
+  let joined_tree = parse(parser, grammar, joined)?;
+  if signature(parser, grammar, &before_tree, before, true)?
+    != signature(parser, grammar, &joined_tree, joined, true)?
+  {
+    return Ok(Some(String::from(
+      "the syntax tree of the joined lines differs from the formatted region",
+    )));
+  }
+
+  Ok(None)
+}
@@ -0,0 +1,147 @@
+use tree_sitter::Parser;
+
+use super::FormatContext;
+use crate::{api::grammar::resolve_language, config::LanguageSpec};
+
+// A minimal line diff between two documents. Lines shared at the start and end are omitted.
+fn diff(first: &str, second: &str) -> String {
+  let first_lines = first.lines().collect::<Vec<_>>();
+  let second_lines = second.lines().collect::<Vec<_>>();
+
+  let prefix = first_lines
+    .iter()
+    .zip(&second_lines)
+    .take_while(|(a, b)| a == b)
+    .count();
+  let suffix = first_lines[prefix..]
+    .iter()
+    .rev()
+    .zip(second_lines[prefix..].iter().rev())
+    .take_while(|(a, b)| a == b)
+    .count();
+
+  let removed = &first_lines[prefix..first_lines.len() - suffix];
+  let added = &second_lines[prefix..second_lines.len() - suffix];
+  if removed.is_empty() && added.is_empty() {
+    return String::from("(the results only differ in trailing newlines)");
+  }
+
+  let mut result = format!("@@ line {} @@\n", prefix + 1);
+  for line in removed {
+    result.push_str(&format!("-{line}\n"));
+  }
+  for line in added {
+    result.push_str(&format!("+{line}\n"));
+  }
+  result
+}
+
+// Find the innermost injected region containing `row`, returning its language and the row it starts
+// at.
+fn region_at_row(
+  parser: &mut Parser,
+  document: &[u8],
+  language: &str,
+  parent_language: Option<&str>,
+  row: usize,
+  format_context: &FormatContext,
+) -> Option<(String, usize)> {
+  let regions = format_context
+    .grammars
+    .get(language)
+    .and_then(|grammar| {
+      crate::api::injections::extract_language_injections(
+        parser,
+        grammar,
+        document,
+        parent_language,
+      )
+      .ok()
+    })
+    .unwrap_or_default()
+    .into_iter()
+    .map(|mut region| {
+      region.lang = resolve_language(
+        &region.lang,
+        format_context.grammars,
+        format_context.languages,
+        format_context.aliases,
+      );
+      region
+    })
+    .collect::<Vec<_>>();
+
+  let region = regions
+    .iter()
+    .filter(|region| region.range.start_point.row <= row && row <= region.range.end_point.row)
+    .min_by_key(|region| region.range.end_byte - region.range.start_byte)?;
+
+  let start_row = region.range.start_point.row;
+  let (language, region_row) = region_at_row(
+    parser,
+    &document[region.range.start_byte..region.range.end_byte],
+    &region.lang,
+    Some(language),
+    row - start_row,
+    format_context,
+  )
+  .unwrap_or((region.lang.clone(), 0));
+
+  Some((language, region_row + start_row))
+}
+
+/// Describe why formatting `first` again produced `second`, naming the innermost region which
+/// changed and the formatters configured for its language.
+pub fn describe(
+  first: &[u8],
+  second: &[u8],
+  language: &str,
+  format_context: &FormatContext,
+) -> String {
+  let first = String::from_utf8_lossy(first);
+  let second = String::from_utf8_lossy(second);
+
+  let row = first
+    .lines()
+    .zip(second.lines())
+    .take_while(|(a, b)| a == b)
+    .count();
+
+  let mut parser = Parser::new();
+  let region = region_at_row(
+    &mut parser,
+    first.as_bytes(),
+    language,
+    None,
+    row,
+    format_context,
+  );
+  let region_language = region
+    .as_ref()
+    .map_or(language, |(region_language, _)| region_language.as_str());
+
+  let formatters = format_context
+    .languages
+    .get(region_language)
+    .map(LanguageSpec::formatters)
+    .unwrap_or_default()
+    .iter()
+    .flat_map(|spec| spec.candidates())
+    .map(|candidate| candidate.formatter())
+    .collect::<Vec<_>>();
+  let formatters = if formatters.is_empty() {
+    String::from("no formatters")
+  } else {
+    format!("formatters: {}", formatters.join(", "))
+  };
+
+  let location = match &region {
+    Some((_, region_row)) => format!("the [{region_language}] region at line {}", region_row + 1),
+    None => format!("the [{language}] document"),
+  };
+
+  format!(
+    "Formatting is not idempotent, {location} changed when formatted again ({formatters})\n{}",
+    diff(&first, &second)
+  )
+}
@@ -0,0 +1,62 @@
+use std::{fmt, path::PathBuf, sync::Mutex};
+
+/// What became of the region a [`FormatWarning`] is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WarningKind {
+  /// A formatter failed, or the region couldn't be formatted at all.
+  Failed,
+  /// The output of the formatters was rejected by a safety check, or dropped placeholders.
+  Rejected,
+  /// The formatted region couldn't be put back into the document the way it was found.
+  Skipped,
+}
+
+/// A region which was left unchanged, as it failed to format with `on_error = "skip"` or its
+/// formatted result couldn't be used.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FormatWarning {
+  pub kind: WarningKind,
+  pub path: Option<PathBuf>,
+  /// The 1-based line in the document at which the region starts.
+  pub line: usize,
+  pub language: String,
+  pub message: String,
+}
+
+// Only formatter failures need describing, the messages of the other kinds say what went wrong.
+impl fmt::Display for FormatWarning {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let path = self
+      .path
+      .as_ref()
+      .map_or("[stdin]".into(), |path| path.to_string_lossy());
+    if self.kind == WarningKind::Failed {
+      write!(f, "Failed to format region ")?;
+    }
+    write!(
+      f,
+      "{path}:{} [{}] {}",
+      self.line, self.language, self.message
+    )
+  }
+}
+
+/// Collects the warnings of every document formatted during a run. Safe to share between threads.
+#[derive(Debug, Default)]
+pub struct FormatWarnings {
+  entries: Mutex<Vec<FormatWarning>>,
+}
+
+impl FormatWarnings {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn extend(&self, warnings: impl IntoIterator<Item = FormatWarning>) {
+    self.entries.lock().unwrap().extend(warnings);
+  }
+
+  pub fn take(&self) -> Vec<FormatWarning> {
+    std::mem::take(&mut self.entries.lock().unwrap())
+  }
+}
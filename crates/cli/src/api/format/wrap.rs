@@ -0,0 +1,82 @@
+use anyhow::Result;
+
+use crate::api::text;
+
+/// Surround a fragment with the `pruner.wrap-prefix` and `pruner.wrap-suffix` of its injection, so
+/// formatters see a whole program.
+pub fn wrap(source: &[u8], prefix: &str, suffix: &str) -> Vec<u8> {
+  let mut wrapped = Vec::with_capacity(prefix.len() + source.len() + suffix.len());
+  wrapped.extend_from_slice(prefix.as_bytes());
+  wrapped.extend_from_slice(source);
+  wrapped.extend_from_slice(suffix.as_bytes());
+  wrapped
+}
+
+fn non_whitespace(text: &str) -> impl DoubleEndedIterator<Item = char> + '_ {
+  text.chars().filter(|char| !char.is_whitespace())
+}
+
+// The byte offset in `text` just after `prefix`, ignoring whitespace in both.
+fn match_prefix(text: &str, prefix: &str) -> Option<usize> {
+  let mut chars = text
+    .char_indices()
+    .filter(|(_, char)| !char.is_whitespace());
+  let mut end = 0;
+  for expected in non_whitespace(prefix) {
+    let (index, char) = chars.next()?;
+    if char != expected {
+      return None;
+    }
+    end = index + char.len_utf8();
+  }
+  Some(end)
+}
+
+// The byte offset in `text` at which `suffix` starts, ignoring whitespace in both.
+fn match_suffix(text: &str, suffix: &str) -> Option<usize> {
+  let mut chars = text
+    .char_indices()
+    .rev()
+    .filter(|(_, char)| !char.is_whitespace());
+  let mut start = text.len();
+  for expected in non_whitespace(suffix).rev() {
+    let (index, char) = chars.next()?;
+    if char != expected {
+      return None;
+    }
+    start = index;
+  }
+  Some(start)
+}
+
+/// Remove the wrapper added by [`wrap`] from formatted output. Formatters may change the whitespace
+/// within and around the wrapper, but anything else is an error.
+///
+/// When the formatter moved the fragment onto lines of its own, the indentation it added relative to
+/// the wrapper is removed too.
+pub fn unwrap(formatted: &[u8], prefix: &str, suffix: &str) -> Result<Vec<u8>> {
+  let formatted = std::str::from_utf8(formatted)?;
+
+  let Some(start) = match_prefix(formatted, prefix) else {
+    anyhow::bail!("The formatter changed the wrap prefix {prefix:?} of the region");
+  };
+  let Some(end) = match_suffix(formatted, suffix) else {
+    anyhow::bail!("The formatter changed the wrap suffix {suffix:?} of the region");
+  };
+  if end < start {
+    anyhow::bail!("The formatter changed the wrapper of the region");
+  }
+
+  let body = &formatted[start..end];
+  let trimmed = body.trim_start();
+  let body = match body[..body.len() - trimmed.len()].rfind('\n') {
+    Some(newline) => &body[newline + 1..],
+    None => trimmed,
+  }
+  .trim_end();
+
+  let indent = text::min_leading_indent(body);
+  let mut result = text::strip_leading_indent(body, &indent, text::DEFAULT_TAB_WIDTH);
+  result.push('\n');
+  Ok(result.into_bytes())
+}
@@ -1,17 +1,21 @@
 use anyhow::{Context, Result};
 use rayon::prelude::*;
+use regex::Regex;
 use std::{collections::HashMap, fs, path::Path, path::PathBuf};
 use tree_sitter::{Language, Query};
 use tree_sitter_loader::{CompileConfig, Loader};
 
 use super::queries;
+use crate::config::{LanguageAliases, LanguageFormatters};
 
 #[derive(Debug)]
 pub struct Grammar {
-  #[allow(dead_code)]
   pub name: String,
   pub lang: Language,
   pub injections: Query,
+  pub file_types: Vec<String>,
+  /// Matches the names injections may use to refer to the grammar.
+  pub injection_regex: Option<Regex>,
 }
 
 pub type Grammars = HashMap<String, Grammar>;
@@ -65,6 +69,8 @@ fn load_grammars_from_path(
         name: config.language_name.clone(),
         lang: language,
         injections: injections_query,
+        file_types: config.file_types.clone(),
+        injection_regex: config.injection_regex.clone(),
       },
     );
   }
@@ -110,3 +116,47 @@ pub fn load_grammars(
 
   Ok(languages)
 }
+
+/// Resolve the name an injection uses for its language to the language it refers to.
+///
+/// Names of known grammars and configured languages are used as they are. Other names are matched
+/// against the `injection-regex` of each grammar, then against their file types. The result is then
+/// mapped through the user's aliases.
+pub fn resolve_language(
+  name: &str,
+  grammars: &Grammars,
+  languages: &LanguageFormatters,
+  aliases: &LanguageAliases,
+) -> String {
+  let resolved = if grammars.contains_key(name) || languages.contains_key(name) {
+    name.to_string()
+  } else {
+    let mut names = grammars.keys().collect::<Vec<_>>();
+    names.sort();
+
+    let by_regex = names.iter().find(|grammar_name| {
+      grammars[grammar_name.as_str()]
+        .injection_regex
+        .as_ref()
+        .is_some_and(|regex| regex.is_match(name))
+    });
+    let by_file_type = || {
+      names.iter().find(|grammar_name| {
+        grammars[grammar_name.as_str()]
+          .file_types
+          .iter()
+          .any(|file_type| file_type == name)
+      })
+    };
+
+    by_regex
+      .or_else(by_file_type)
+      .map_or_else(|| name.to_string(), |grammar_name| grammar_name.to_string())
+  };
+
+  aliases
+    .get(&resolved)
+    .or_else(|| aliases.get(name))
+    .cloned()
+    .unwrap_or(resolved)
+}
@@ -1,23 +1,29 @@
 use anyhow::Result;
+use regex::Regex;
 use std::{
   borrow::Cow,
   collections::{HashMap, HashSet},
   ops::Deref,
+  path::Path,
 };
 use tree_sitter::{
-  Parser, Point, QueryCursor, QueryPredicate, QueryPredicateArg, QueryProperty, Range,
-  StreamingIterator,
+  Node, Parser, Point, QueryCapture, QueryCursor, QueryMatch, QueryPredicate, QueryPredicateArg,
+  QueryProperty, Range, StreamingIterator,
 };
 
-use super::grammar::Grammar;
+use super::{grammar::Grammar, text::EscapeStyle};
+
+mod lua_pattern;
 
 pub fn get_lang_name(properties: &[QueryProperty]) -> Option<String> {
-  for property in properties {
-    if property.key.deref() == "injection.language" {
-      return property.value.clone().map(String::from);
-    }
-  }
-  None
+  get_property(properties, "injection.language").map(String::from)
+}
+
+fn get_property<'a>(properties: &'a [QueryProperty], key: &str) -> Option<&'a str> {
+  properties
+    .iter()
+    .find(|property| property.key.deref() == key)
+    .and_then(|property| property.value.as_deref())
 }
 
 #[derive(Debug)]
@@ -108,6 +114,61 @@ fn get_escape_modifiers(predicates: &[QueryPredicate]) -> HashMap<u32, HashSet<S
   map
 }
 
+fn get_strip_prefix_modifiers(predicates: &[QueryPredicate]) -> HashMap<u32, &str> {
+  let mut map = HashMap::new();
+  for pred in predicates {
+    if pred.operator.deref() != "strip-prefix!" {
+      continue;
+    }
+
+    let [
+      QueryPredicateArg::Capture(capture),
+      QueryPredicateArg::String(prefix),
+    ] = pred.args.deref()
+    else {
+      continue;
+    };
+
+    map.insert(*capture, prefix.deref());
+  }
+
+  map
+}
+
+fn get_escape_style_modifiers(
+  predicates: &[QueryPredicate],
+) -> HashMap<u32, (EscapeStyle, HashSet<String>)> {
+  let mut map = HashMap::new();
+  for pred in predicates {
+    if pred.operator.deref() != "escape-style!" {
+      continue;
+    }
+
+    let [
+      QueryPredicateArg::Capture(capture),
+      QueryPredicateArg::String(style),
+      chars @ ..,
+    ] = pred.args.deref()
+    else {
+      continue;
+    };
+    let Some(style) = EscapeStyle::from_name(style) else {
+      continue;
+    };
+    let chars = chars
+      .iter()
+      .filter_map(|arg| match arg {
+        QueryPredicateArg::String(value) => Some(value.to_string()),
+        QueryPredicateArg::Capture(_) => None,
+      })
+      .collect();
+
+    map.insert(*capture, (style, chars));
+  }
+
+  map
+}
+
 fn point_to_byte(source: &str, point: Point) -> Option<usize> {
   let mut byte_index = 0;
 
@@ -176,34 +237,296 @@ fn remap_range_for_appended_newline(range: Range, original_endpoint: &Option<End
   }
 }
 
-fn calculate_point_offset(value: usize, offset: isize) -> usize {
-  ((value as isize) + offset) as usize
+fn calculate_point_offset(value: usize, offset: isize) -> Option<usize> {
+  value.checked_add_signed(offset)
 }
 
-fn apply_offset_to_range(source: &str, range: &Range, offset: &RangeOffset) -> Range {
+// Returns `None` when the offset moves the range out of bounds of the source
+fn apply_offset_to_range(source: &str, range: &Range, offset: &RangeOffset) -> Option<Range> {
   let new_start_point = Point {
-    row: calculate_point_offset(range.start_point.row, offset.start_row),
-    column: calculate_point_offset(range.start_point.column, offset.start_col),
+    row: calculate_point_offset(range.start_point.row, offset.start_row)?,
+    column: calculate_point_offset(range.start_point.column, offset.start_col)?,
   };
   let new_end_point = Point {
-    row: calculate_point_offset(range.end_point.row, offset.end_row),
-    column: calculate_point_offset(range.end_point.column, offset.end_col),
+    row: calculate_point_offset(range.end_point.row, offset.end_row)?,
+    column: calculate_point_offset(range.end_point.column, offset.end_col)?,
   };
 
-  let new_start_byte = point_to_byte(source, new_start_point).unwrap();
-  let new_end_byte = point_to_byte(source, new_end_point).unwrap();
+  let new_start_byte = point_to_byte(source, new_start_point)?;
+  let new_end_byte = point_to_byte(source, new_end_point)?;
+  if new_start_byte > new_end_byte {
+    return None;
+  }
 
-  Range {
+  Some(Range {
     start_byte: new_start_byte,
     end_byte: new_end_byte,
-    start_point: new_start_point,
-    end_point: new_end_point,
+    start_point: point_for_byte(source.as_bytes(), new_start_byte),
+    end_point: point_for_byte(source.as_bytes(), new_end_byte),
+  })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Trim {
+  start_linewise: bool,
+  start_charwise: bool,
+  end_linewise: bool,
+  end_charwise: bool,
+}
+
+fn parse_trim_predicate(pred: &QueryPredicate) -> Result<(u32, Trim)> {
+  let Some(QueryPredicateArg::Capture(capture)) = pred.args.first() else {
+    anyhow::bail!("Trim predicate requires capture as first argument");
+  };
+
+  // A bare `#trim!` only trims trailing blank lines
+  if pred.args.len() == 1 {
+    let trim = Trim {
+      start_linewise: false,
+      start_charwise: false,
+      end_linewise: true,
+      end_charwise: false,
+    };
+    return Ok((*capture, trim));
+  }
+
+  let flags = pred.args[1..]
+    .iter()
+    .map(|arg| match arg {
+      QueryPredicateArg::String(value) => Ok(value.deref() != "0"),
+      QueryPredicateArg::Capture(_) => {
+        anyhow::bail!("Trim predicate only supports string arguments")
+      }
+    })
+    .collect::<Result<Vec<_>>>()?;
+  let [start_linewise, start_charwise, end_linewise, end_charwise] = flags[..] else {
+    anyhow::bail!("Trim predicate requires 1 or 5 arguments");
+  };
+
+  let trim = Trim {
+    start_linewise,
+    start_charwise,
+    end_linewise,
+    end_charwise,
+  };
+  Ok((*capture, trim))
+}
+
+fn get_trim_modifiers(predicates: &[QueryPredicate]) -> HashMap<u32, Trim> {
+  let mut map = HashMap::new();
+  for pred in predicates {
+    if pred.operator.deref() != "trim!" {
+      continue;
+    }
+
+    let Ok((capture, trim)) = parse_trim_predicate(pred) else {
+      continue;
+    };
+
+    map.insert(capture, trim);
   }
+
+  map
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+fn apply_trim_to_range(source: &[u8], range: &Range, trim: &Trim) -> Range {
+  let mut start = range.start_byte;
+  let mut end = range.end_byte;
+  let text = &source[start..end];
+
+  let Some(first_content) = text.iter().position(|byte| !byte.is_ascii_whitespace()) else {
+    return Range {
+      start_byte: start,
+      end_byte: start,
+      start_point: range.start_point,
+      end_point: range.start_point,
+    };
+  };
+  let last_content = text
+    .iter()
+    .rposition(|byte| !byte.is_ascii_whitespace())
+    .unwrap_or(first_content);
+
+  if trim.end_charwise {
+    end = range.start_byte + last_content + 1;
+  } else if trim.end_linewise
+    && let Some(newline) = text[last_content..].iter().position(|byte| *byte == b'\n')
+  {
+    end = range.start_byte + last_content + newline;
+  }
+
+  if trim.start_charwise {
+    start = range.start_byte + first_content;
+  } else if trim.start_linewise
+    && let Some(newline) = text[..first_content]
+      .iter()
+      .rposition(|byte| *byte == b'\n')
+  {
+    start = range.start_byte + newline + 1;
+  }
+
+  Range {
+    start_byte: start,
+    end_byte: end,
+    start_point: point_for_byte(source, start),
+    end_point: point_for_byte(source, end),
+  }
+}
+
+fn get_gsub_modifiers(predicates: &[QueryPredicate]) -> HashMap<u32, Vec<(&str, &str)>> {
+  let mut map: HashMap<u32, Vec<(&str, &str)>> = HashMap::new();
+  for pred in predicates {
+    if pred.operator.deref() != "gsub!" {
+      continue;
+    }
+
+    let [
+      QueryPredicateArg::Capture(capture),
+      QueryPredicateArg::String(pattern),
+      QueryPredicateArg::String(replacement),
+    ] = pred.args.deref()
+    else {
+      continue;
+    };
+
+    map
+      .entry(*capture)
+      .or_default()
+      .push((pattern.deref(), replacement.deref()));
+  }
+
+  map
+}
+
+// Compiled lua patterns, shared by all matches of a query.
+#[derive(Default)]
+struct LuaPatterns {
+  compiled: HashMap<String, Regex>,
+}
+
+impl LuaPatterns {
+  fn get(&mut self, pattern: &str) -> Result<&Regex> {
+    if !self.compiled.contains_key(pattern) {
+      let regex = lua_pattern::to_regex(pattern)?;
+      self.compiled.insert(pattern.to_string(), regex);
+    }
+    Ok(&self.compiled[pattern])
+  }
+}
+
+// Evaluates the `#lua-match?` and `#not-lua-match?` predicates, which tree-sitter leaves to the
+// caller.
+fn satisfies_lua_predicates(
+  predicates: &[QueryPredicate],
+  query_match: &QueryMatch,
+  source: &[u8],
+  patterns: &mut LuaPatterns,
+) -> Result<bool> {
+  for pred in predicates {
+    let negated = match pred.operator.deref() {
+      "lua-match?" => false,
+      "not-lua-match?" => true,
+      _ => continue,
+    };
+
+    let [
+      QueryPredicateArg::Capture(capture),
+      QueryPredicateArg::String(pattern),
+    ] = pred.args.deref()
+    else {
+      anyhow::bail!(
+        "{} predicate requires a capture and a pattern",
+        pred.operator
+      );
+    };
+
+    let regex = patterns.get(pattern)?;
+    for node in query_match.nodes_for_capture_index(*capture) {
+      let text = node.utf8_text(source)?;
+      if regex.is_match(text) == negated {
+        return Ok(false);
+      }
+    }
+  }
+
+  Ok(true)
+}
+
+/// How the lines of a formatted region are indented when it is put back, set by `pruner.indent`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum RegionIndent {
+  /// Lines line up with the column the region starts at, or keep the indentation of its content
+  /// when it starts at the beginning of a line.
+  #[default]
+  Auto,
+  /// `"align"`, lines line up with the column the region starts at, such as just after an opening
+  /// delimiter.
+  Align,
+  /// A number of spaces, lines are indented by that much more than the line the region starts on.
+  /// Regions starting mid-line get a hanging indent.
+  Block(usize),
+}
+
+impl RegionIndent {
+  fn parse(value: &str) -> Option<Self> {
+    match value {
+      "auto" => Some(Self::Auto),
+      "align" => Some(Self::Align),
+      width => width.parse().ok().map(Self::Block),
+    }
+  }
+}
+
+// The number of newlines set by a `pruner.leading-newline` or `pruner.trailing-newline` property,
+// either a count or a boolean.
+fn parse_newlines(value: Option<&str>) -> Option<usize> {
+  match value? {
+    "true" => Some(1),
+    "false" => Some(0),
+    count => count.parse().ok(),
+  }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct InjectionOpts {
   pub escape_chars: HashSet<String>,
+  /// Set by `(#escape-style! @injection.content "doubled" "'")`, how `escape_chars` are escaped.
+  pub escape_style: EscapeStyle,
+  /// Names of the other captures in the matched pattern, excluding `injection.*` and `pruner.*`
+  /// captures. Sorted and deduplicated.
+  pub captures: Vec<String>,
+  /// Set by `(#set! pruner.formatter "name")`, formats the region with this formatter instead of
+  /// those configured for its language.
+  pub formatter: Option<String>,
+  /// Set by `(#set! pruner.print-width 60)`, the column formatted lines of the region should end by.
+  pub print_width: Option<u32>,
+  /// Set by `(#set! pruner.skip true)`, leaves the region as it is.
+  pub skip: bool,
+  /// Set by `(#set! pruner.single-line true)` for regions within strings which can't hold newlines.
+  /// The region is formatted without a print width and its lines are joined.
+  pub single_line: bool,
+  /// Set by `(#set! pruner.indent "align")`, see [`RegionIndent`].
+  pub indent: RegionIndent,
+  /// Set by `(#set! pruner.leading-newline true)`, the number of newlines the formatted region
+  /// starts with, replacing those produced by the formatter.
+  pub leading_newlines: Option<usize>,
+  /// Set by `(#set! pruner.trailing-newline 2)`, the number of newlines the formatted region ends
+  /// with, replacing those the region originally ended with.
+  pub trailing_newlines: Option<usize>,
+  /// Set by `(#set! pruner.wrap-prefix "const _ = ")`, added before fragments so that formatters
+  /// accept them, and removed again afterwards.
+  pub wrap_prefix: Option<String>,
+  /// Set by `(#set! pruner.wrap-suffix ";")`, the counterpart of `wrap_prefix`.
+  pub wrap_suffix: Option<String>,
+  /// Set by `(#strip-prefix! @injection.content "/// ")`, a prefix repeated on every line of the
+  /// region such as a comment marker. It is removed before formatting and added back afterwards.
+  pub line_prefix: Option<String>,
+  /// The text of each `@pruner.<name>` capture by name, available to formatter arguments as
+  /// `$<name>`. Sorted by name.
+  pub variables: Vec<(String, String)>,
+  /// The byte ranges of the `@pruner.placeholder` captures within the region, such as the
+  /// substitutions of a template string. They are masked while the region is formatted.
+  pub placeholders: Vec<std::ops::Range<usize>>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -211,12 +534,231 @@ pub struct InjectedRegion {
   pub range: Range,
   pub lang: String,
   pub opts: InjectionOpts,
+  /// The ranges of the nodes making up the region, in order. Only injections marked with
+  /// `injection.combined`, or whose content node has children which aren't included, have more
+  /// than one, in which case `range` spans all of them.
+  pub parts: Vec<Range>,
+  /// How many other regions of the document this region lies within. Nested regions are formatted
+  /// before the regions containing them.
+  pub nesting: usize,
+}
+
+impl InjectedRegion {
+  /// Whether every part of `child` lies within a part of this region, and they aren't the same
+  /// parts.
+  pub fn contains(&self, child: &InjectedRegion) -> bool {
+    self.parts != child.parts
+      && child.parts.iter().all(|child| {
+        self
+          .parts
+          .iter()
+          .any(|parent| parent.start_byte <= child.start_byte && child.end_byte <= parent.end_byte)
+      })
+  }
+}
+
+// Joins a region into the combined region of the same pattern and language.
+fn combine_region(combined: &mut InjectedRegion, region: InjectedRegion) {
+  if region.range.start_byte < combined.range.start_byte {
+    combined.range.start_byte = region.range.start_byte;
+    combined.range.start_point = region.range.start_point;
+  }
+  if region.range.end_byte > combined.range.end_byte {
+    combined.range.end_byte = region.range.end_byte;
+    combined.range.end_point = region.range.end_point;
+  }
+
+  combined.opts.escape_chars.extend(region.opts.escape_chars);
+  combined.opts.captures.extend(region.opts.captures);
+  combined.opts.captures.sort();
+  combined.opts.captures.dedup();
+  combined.opts.variables.extend(region.opts.variables);
+  combined.opts.variables.sort();
+  combined.opts.variables.dedup_by(|a, b| a.0 == b.0);
+  combined.opts.placeholders.extend(region.opts.placeholders);
+  combined.opts.placeholders.sort_by_key(|range| range.start);
+  combined.opts.placeholders.dedup();
+
+  combined.parts.extend(region.parts);
+  combined.parts.sort_by_key(|part| part.start_byte);
+  combined.parts.dedup();
+}
+
+struct Rank {
+  priority: i64,
+  pattern_index: usize,
+}
+
+fn parts_overlap(a: &InjectedRegion, b: &InjectedRegion) -> bool {
+  a.parts.iter().any(|a| {
+    b.parts
+      .iter()
+      .any(|b| a.start_byte < b.end_byte && b.start_byte < a.end_byte)
+  })
+}
+
+fn describe_region(region: &InjectedRegion) -> String {
+  format!(
+    "[{}] region at {}:{}",
+    region.lang,
+    region.range.start_point.row + 1,
+    region.range.start_point.column + 1
+  )
+}
+
+// Make sure no two regions cover the same text, unless one lies within the other, so their results
+// can't be spliced over each other.
+//
+// Exact duplicates are dropped. Regions which overlap without one containing the other are
+// resolved by their `priority` property, and then by which pattern comes first in the query. The
+// regions which are kept record how deeply they are nested within each other.
+fn resolve_overlaps(regions: Vec<InjectedRegion>, ranks: &[Rank]) -> Vec<InjectedRegion> {
+  let mut order = (0..regions.len()).collect::<Vec<_>>();
+  order.sort_by(|a, b| {
+    let (a_rank, b_rank) = (&ranks[*a], &ranks[*b]);
+    b_rank
+      .priority
+      .cmp(&a_rank.priority)
+      .then(a_rank.pattern_index.cmp(&b_rank.pattern_index))
+      .then(a.cmp(b))
+  });
+
+  let mut kept: Vec<usize> = Vec::new();
+  for index in order {
+    let region = &regions[index];
+    if let Some(duplicate) = kept
+      .iter()
+      .find(|kept| regions[**kept].parts == region.parts && regions[**kept].lang == region.lang)
+    {
+      log::warn!(
+        "Dropping duplicate {}, matched by patterns {} and {}",
+        describe_region(region),
+        ranks[*duplicate].pattern_index,
+        ranks[index].pattern_index
+      );
+      continue;
+    }
+
+    if let Some(conflict) = kept.iter().find(|kept| {
+      let kept = &regions[**kept];
+      parts_overlap(kept, region) && !kept.contains(region) && !region.contains(kept)
+    }) {
+      log::warn!(
+        "Dropping {}, it overlaps the {} which takes precedence",
+        describe_region(region),
+        describe_region(&regions[*conflict])
+      );
+      continue;
+    }
+
+    kept.push(index);
+  }
+
+  kept.sort();
+  let nesting = kept
+    .iter()
+    .map(|child| {
+      kept
+        .iter()
+        .filter(|parent| regions[**parent].contains(&regions[*child]))
+        .count()
+    })
+    .collect::<Vec<_>>();
+  let mut kept = kept.into_iter().zip(nesting).peekable();
+  regions
+    .into_iter()
+    .enumerate()
+    .filter_map(|(index, region)| {
+      let (_, nesting) = kept.next_if(|(kept, _)| *kept == index)?;
+      Some(InjectedRegion { nesting, ..region })
+    })
+    .collect()
+}
+
+// The language of an `injection.filename` capture is named by its extension, or by the whole name
+// for files without one. Either is resolved through the file types of the grammars.
+fn filename_language(filename: &str) -> Option<String> {
+  let path = Path::new(filename.trim());
+  path
+    .extension()
+    .or_else(|| path.file_name())
+    .and_then(|name| name.to_str())
+    .map(String::from)
+}
+
+fn has_property(properties: &[QueryProperty], key: &str) -> bool {
+  properties
+    .iter()
+    .any(|property| property.key.deref() == key)
+}
+
+// Without `injection.include-children` the children of the content node are not part of the
+// injection, the same as with nvim-treesitter. Children which are only whitespace are left in, as
+// indentation handling already takes care of them.
+fn content_children(
+  node: &Node,
+  source: &[u8],
+  placeholders: &[std::ops::Range<usize>],
+) -> Vec<Range> {
+  let mut cursor = node.walk();
+  node
+    .named_children(&mut cursor)
+    .filter(|child| {
+      !placeholders.contains(&child.byte_range())
+        && source[child.byte_range()]
+          .iter()
+          .any(|byte| !byte.is_ascii_whitespace())
+    })
+    .map(|child| child.range())
+    .collect()
 }
 
+// Cut `children` out of `range`, leaving the parts of the region between them. Parts which are only
+// whitespace are left out.
+fn cut_children(range: Range, children: &[Range], source: &[u8]) -> Vec<Range> {
+  let mut parts = Vec::new();
+  let (mut start_byte, mut start_point) = (range.start_byte, range.start_point);
+  for child in children {
+    if child.end_byte <= start_byte || child.start_byte >= range.end_byte {
+      continue;
+    }
+    if child.start_byte > start_byte {
+      parts.push(Range {
+        start_byte,
+        start_point,
+        end_byte: child.start_byte,
+        end_point: child.start_point,
+      });
+    }
+    start_byte = child.end_byte;
+    start_point = child.end_point;
+  }
+  if range.end_byte > start_byte {
+    parts.push(Range {
+      start_byte,
+      start_point,
+      end_byte: range.end_byte,
+      end_point: range.end_point,
+    });
+  }
+
+  parts.retain(|part| {
+    source[part.start_byte..part.end_byte]
+      .iter()
+      .any(|byte| !byte.is_ascii_whitespace())
+  });
+  parts
+}
+
+/// Extract the regions of `source` which should be formatted as another language.
+///
+/// `parent_language` is the language `source` was itself injected into, if any, and is what
+/// `injection.parent` resolves to.
 pub fn extract_language_injections(
   parser: &mut Parser,
   grammar: &Grammar,
   source: &[u8],
+  parent_language: Option<&str>,
 ) -> Result<Vec<InjectedRegion>> {
   let (source_with_newline, original_endpoint) = with_newline(source);
   let source_str = String::from_utf8(Vec::from(source_with_newline.as_ref()))?;
@@ -227,42 +769,88 @@ pub fn extract_language_injections(
     .ok_or_else(|| anyhow::anyhow!("Parse returned None"))?;
 
   let mut injected_regions = Vec::new();
+  // The priority and pattern index of each region, to resolve overlaps by
+  let mut ranks = Vec::new();
 
   let query = &grammar.injections;
 
   let mut cursor = QueryCursor::new();
   let mut matches = cursor.matches(query, tree.root_node(), source_with_newline.as_ref());
+  let mut lua_patterns = LuaPatterns::default();
+  // Indices of the combined region for each pattern and language
+  let mut combined_regions: HashMap<(usize, String), usize> = HashMap::new();
 
   let lang_capture_index = query.capture_index_for_name("injection.language");
+  let filename_capture_index = query.capture_index_for_name("injection.filename");
   let Some(content_capture_index) = query.capture_index_for_name("injection.content") else {
     return Ok(injected_regions);
   };
 
   while let Some(query_match) = matches.next() {
-    let harcoded_lang_name = get_lang_name(query.property_settings(query_match.pattern_index));
+    let predicates = query.general_predicates(query_match.pattern_index);
+    if !satisfies_lua_predicates(
+      predicates,
+      query_match,
+      source_with_newline.as_ref(),
+      &mut lua_patterns,
+    )? {
+      continue;
+    }
+
+    let properties = query.property_settings(query_match.pattern_index);
+    let harcoded_lang_name = if has_property(properties, "injection.self") {
+      Some(grammar.name.clone())
+    } else if has_property(properties, "injection.parent") {
+      Some(parent_language.unwrap_or(&grammar.name).to_string())
+    } else {
+      get_lang_name(properties)
+    };
 
     let mut lang_capture = None;
+    let mut filename_capture = None;
     let mut content_capture = None;
     for capture in query_match.captures {
-      if let Some(lang_capture_index) = lang_capture_index {
-        if capture.index == lang_capture_index {
-          lang_capture = Some(capture);
-        }
+      if let Some(lang_capture_index) = lang_capture_index
+        && capture.index == lang_capture_index
+      {
+        lang_capture = Some(capture);
+      }
+      if let Some(filename_capture_index) = filename_capture_index
+        && capture.index == filename_capture_index
+      {
+        filename_capture = Some(capture);
       }
       if capture.index == content_capture_index {
         content_capture = Some(capture);
       }
     }
 
-    let Some(lang_name) = harcoded_lang_name.or_else(|| {
-      lang_capture.and_then(|capture| {
-        capture
-          .node
-          .utf8_text(source_with_newline.as_ref())
-          .ok()
-          .map(String::from)
-      })
-    }) else {
+    let gsub_modifiers = get_gsub_modifiers(predicates);
+    let mut capture_text = |capture: &QueryCapture| -> Result<String> {
+      let mut text = capture
+        .node
+        .utf8_text(source_with_newline.as_ref())?
+        .to_string();
+      for (pattern, replacement) in gsub_modifiers.get(&capture.index).into_iter().flatten() {
+        text = lua_pattern::gsub(lua_patterns.get(pattern)?, &text, replacement);
+      }
+      Ok(text)
+    };
+
+    let lang_name = if let Some(lang_name) = harcoded_lang_name {
+      Some(lang_name)
+    } else if let Some(capture) = lang_capture {
+      // Only the first word of info strings such as `sql postgres` names the language
+      capture_text(capture)?
+        .split_whitespace()
+        .next()
+        .map(String::from)
+    } else if let Some(capture) = filename_capture {
+      filename_language(&capture_text(capture)?)
+    } else {
+      None
+    };
+    let Some(lang_name) = lang_name else {
       continue;
     };
 
@@ -270,27 +858,159 @@ pub fn extract_language_injections(
       continue;
     };
 
-    let predicates = query.general_predicates(query_match.pattern_index);
+    // Children captured as placeholders don't count as children of the content
+    let mut placeholders = query_match
+      .captures
+      .iter()
+      .filter(|capture| query.capture_names()[capture.index as usize] == "pruner.placeholder")
+      .map(|capture| capture.node.byte_range())
+      .collect::<Vec<_>>();
+
+    let children = if has_property(properties, "injection.include-children") {
+      Vec::new()
+    } else {
+      content_children(
+        &content_capture.node,
+        source_with_newline.as_ref(),
+        &placeholders,
+      )
+    };
+
     let offset_modifiers = get_offset_modifiers(predicates);
     let escape_modifiers = get_escape_modifiers(predicates);
+    let escape_style_modifiers = get_escape_style_modifiers(predicates);
+    let trim_modifiers = get_trim_modifiers(predicates);
+    let strip_prefix_modifiers = get_strip_prefix_modifiers(predicates);
 
     let range = if let Some(offset) = offset_modifiers.get(&content_capture.index) {
-      apply_offset_to_range(&source_str, &content_capture.node.range(), offset)
+      let Some(range) = apply_offset_to_range(&source_str, &content_capture.node.range(), offset)
+      else {
+        continue;
+      };
+      range
     } else {
       content_capture.node.range()
     };
 
-    let escape_chars = escape_modifiers
+    let range = if let Some(trim) = trim_modifiers.get(&content_capture.index) {
+      let range = apply_trim_to_range(source_with_newline.as_ref(), &range, trim);
+      if range.start_byte == range.end_byte {
+        continue;
+      }
+      range
+    } else {
+      range
+    };
+
+    let mut escape_chars = escape_modifiers
       .get(&content_capture.index)
       .cloned()
       .unwrap_or_default();
+    let escape_style = match escape_style_modifiers.get(&content_capture.index) {
+      Some((style, chars)) => {
+        escape_chars.extend(chars.iter().cloned());
+        *style
+      }
+      None => EscapeStyle::Backslash,
+    };
 
-    injected_regions.push(InjectedRegion {
+    let mut captures = query_match
+      .captures
+      .iter()
+      .map(|capture| query.capture_names()[capture.index as usize])
+      .filter(|name| !name.starts_with("injection.") && !name.starts_with("pruner."))
+      .map(String::from)
+      .collect::<Vec<_>>();
+    captures.sort();
+    captures.dedup();
+
+    let mut variables = Vec::new();
+    for capture in query_match.captures {
+      let name = query.capture_names()[capture.index as usize];
+      if let Some(name) = name.strip_prefix("pruner.")
+        && name != "placeholder"
+      {
+        variables.push((name.to_string(), capture_text(capture)?));
+      }
+    }
+    variables.sort();
+    variables.dedup_by(|a, b| a.0 == b.0);
+
+    let range = remap_range_for_appended_newline(range, &original_endpoint);
+    // Regions with children cut out of them are made up of the parts between the children, and are
+    // formatted the same as combined regions
+    let (range, parts) = if children.is_empty() {
+      (range, vec![range])
+    } else {
+      match cut_children(range, &children, source_with_newline.as_ref()).as_slice() {
+        [] => {
+          log::debug!(
+            "Skipping the injection matched by pattern {} at line {}, its {} node only holds \
+             children. Set injection.include-children to include them",
+            query_match.pattern_index,
+            content_capture.node.start_position().row + 1,
+            content_capture.node.kind()
+          );
+          continue;
+        }
+        [part] => (*part, vec![*part]),
+        parts => (range, parts.to_vec()),
+      }
+    };
+    placeholders.retain(|placeholder| {
+      placeholder.start >= range.start_byte && placeholder.end <= range.end_byte
+    });
+    placeholders.sort_by_key(|placeholder| placeholder.start);
+    placeholders.dedup();
+    let region = InjectedRegion {
       lang: lang_name.clone(),
-      range: remap_range_for_appended_newline(range, &original_endpoint),
-      opts: InjectionOpts { escape_chars },
+      range,
+      opts: InjectionOpts {
+        escape_chars,
+        escape_style,
+        captures,
+        formatter: get_property(properties, "pruner.formatter").map(String::from),
+        print_width: get_property(properties, "pruner.print-width")
+          .and_then(|print_width| print_width.parse().ok()),
+        skip: has_property(properties, "pruner.skip")
+          && get_property(properties, "pruner.skip") != Some("false"),
+        single_line: has_property(properties, "pruner.single-line")
+          && get_property(properties, "pruner.single-line") != Some("false"),
+        indent: get_property(properties, "pruner.indent")
+          .and_then(RegionIndent::parse)
+          .unwrap_or_default(),
+        leading_newlines: parse_newlines(get_property(properties, "pruner.leading-newline")),
+        trailing_newlines: parse_newlines(get_property(properties, "pruner.trailing-newline")),
+        wrap_prefix: get_property(properties, "pruner.wrap-prefix").map(String::from),
+        wrap_suffix: get_property(properties, "pruner.wrap-suffix").map(String::from),
+        line_prefix: strip_prefix_modifiers
+          .get(&content_capture.index)
+          .map(|prefix| prefix.to_string()),
+        variables,
+        placeholders,
+      },
+      parts,
+      nesting: 0,
+    };
+
+    if has_property(properties, "injection.combined") {
+      let key = (query_match.pattern_index, lang_name);
+      if let Some(index) = combined_regions.get(&key) {
+        combine_region(&mut injected_regions[*index], region);
+        continue;
+      }
+      combined_regions.insert(key, injected_regions.len());
+    }
+
+    let priority = get_property(properties, "priority")
+      .and_then(|priority| priority.parse::<i64>().ok())
+      .unwrap_or_default();
+    ranks.push(Rank {
+      priority,
+      pattern_index: query_match.pattern_index,
     });
+    injected_regions.push(region);
   }
 
-  Ok(injected_regions)
+  Ok(resolve_overlaps(injected_regions, &ranks))
 }
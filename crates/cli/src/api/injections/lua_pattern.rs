@@ -0,0 +1,183 @@
+use anyhow::Result;
+use regex::{Captures, Regex};
+
+fn class_name(class: char) -> Option<&'static str> {
+  let name = match class.to_ascii_lowercase() {
+    'a' => "alpha",
+    'c' => "cntrl",
+    'd' => "digit",
+    'g' => "graph",
+    'l' => "lower",
+    'p' => "punct",
+    's' => "space",
+    'u' => "upper",
+    'w' => "alnum",
+    'x' => "xdigit",
+    _ => return None,
+  };
+  Some(name)
+}
+
+// A `%x` class for use within a regex bracket expression. Uppercase classes are the complement.
+fn set_class(class: char) -> Option<String> {
+  let name = class_name(class)?;
+  let negation = if class.is_ascii_uppercase() { "^" } else { "" };
+  Some(format!("[:{negation}{name}:]"))
+}
+
+fn set_literal(char: char) -> String {
+  match char {
+    '\\' | '[' | ']' | '&' | '~' | '-' | '^' => format!("\\{char}"),
+    _ => char.to_string(),
+  }
+}
+
+fn translate_set(chars: &[char], mut index: usize, out: &mut String) -> Result<usize> {
+  out.push('[');
+  if chars.get(index) == Some(&'^') {
+    out.push('^');
+    index += 1;
+  }
+
+  let start = index;
+  loop {
+    let Some(&char) = chars.get(index) else {
+      anyhow::bail!("Unterminated set in lua pattern");
+    };
+
+    match char {
+      ']' if index > start => return Ok(index + 1),
+      '%' => {
+        let Some(&escaped) = chars.get(index + 1) else {
+          anyhow::bail!("Lua pattern ends with '%'");
+        };
+        match set_class(escaped) {
+          Some(class) => out.push_str(&class),
+          None => out.push_str(&set_literal(escaped)),
+        }
+        index += 2;
+      }
+      '-' if index > start && chars.get(index + 1).is_some_and(|next| *next != ']') => {
+        out.push('-');
+        index += 1;
+      }
+      _ => {
+        out.push_str(&set_literal(char));
+        index += 1;
+      }
+    }
+  }
+}
+
+/// Translate a Lua pattern, as used by the `#lua-match?` and `#gsub!` directives of the
+/// nvim-treesitter query dialect, into a regex.
+///
+/// Balanced matches (`%b`), frontier patterns (`%f`) and back-references are not supported.
+pub fn to_regex(pattern: &str) -> Result<Regex> {
+  let chars = pattern.chars().collect::<Vec<_>>();
+  let mut out = String::from("(?s)");
+  let mut index = 0;
+  // Whether the previous token was a single character class, which a quantifier can apply to.
+  let mut quantifiable = false;
+
+  while let Some(&char) = chars.get(index) {
+    match char {
+      '^' if index == 0 => {
+        out.push('^');
+        index += 1;
+        quantifiable = false;
+        continue;
+      }
+      '$' if index == chars.len() - 1 => {
+        out.push('$');
+        index += 1;
+        continue;
+      }
+      '*' | '+' | '?' if quantifiable => {
+        out.push(char);
+        index += 1;
+        quantifiable = false;
+        continue;
+      }
+      '-' if quantifiable => {
+        out.push_str("*?");
+        index += 1;
+        quantifiable = false;
+        continue;
+      }
+      _ => {}
+    }
+
+    match char {
+      '%' => {
+        let Some(&escaped) = chars.get(index + 1) else {
+          anyhow::bail!("Lua pattern ends with '%'");
+        };
+        if escaped == 'b' || escaped == 'f' || escaped.is_ascii_digit() {
+          anyhow::bail!("Unsupported lua pattern item '%{escaped}'");
+        }
+        match set_class(escaped) {
+          Some(class) => out.push_str(&format!("[{class}]")),
+          None => out.push_str(&regex::escape(&escaped.to_string())),
+        }
+        index += 2;
+        quantifiable = true;
+      }
+      '[' => {
+        index = translate_set(&chars, index + 1, &mut out)?;
+        out.push(']');
+        quantifiable = true;
+      }
+      '.' => {
+        out.push('.');
+        index += 1;
+        quantifiable = true;
+      }
+      '(' | ')' => {
+        out.push(char);
+        index += 1;
+        quantifiable = false;
+      }
+      _ => {
+        out.push_str(&regex::escape(&char.to_string()));
+        index += 1;
+        quantifiable = true;
+      }
+    }
+  }
+
+  Regex::new(&out).map_err(|err| anyhow::anyhow!("Invalid lua pattern {pattern:?}: {err}"))
+}
+
+/// Replace every match of `regex` in `text`, expanding `%0`-`%9` in the Lua style replacement.
+pub fn gsub(regex: &Regex, text: &str, replacement: &str) -> String {
+  regex
+    .replace_all(text, |captures: &Captures| {
+      let mut out = String::new();
+      let mut chars = replacement.chars();
+      while let Some(char) = chars.next() {
+        if char != '%' {
+          out.push(char);
+          continue;
+        }
+        match chars.next() {
+          Some(digit @ '0'..='9') => {
+            let group = digit as usize - '0' as usize;
+            // Like Lua, `%1` refers to the whole match when the pattern has no captures
+            let group = if group == 1 && captures.len() == 1 {
+              0
+            } else {
+              group
+            };
+            if let Some(matched) = captures.get(group) {
+              out.push_str(matched.as_str());
+            }
+          }
+          Some(other) => out.push(other),
+          None => out.push('%'),
+        }
+      }
+      out
+    })
+    .into_owned()
+}
@@ -2,5 +2,6 @@ pub mod format;
 pub mod git;
 pub mod grammar;
 pub mod injections;
+pub mod overrides;
 pub mod queries;
 pub mod text;
@@ -0,0 +1,142 @@
+use anyhow::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::{
+  collections::HashMap,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+};
+
+use crate::{
+  api::format::FormatCache,
+  config::{FormatterSpecs, LanguageFormatters, OverrideConfig},
+};
+
+struct CompiledOverride {
+  files: GlobSet,
+  exclude: GlobSet,
+  config: OverrideConfig,
+}
+
+/// Path-scoped config overrides, compiled once per run and resolved for each formatted file.
+#[derive(Default)]
+pub struct Overrides {
+  root: PathBuf,
+  overrides: Vec<CompiledOverride>,
+  // The settings resolved for each distinct set of matching overrides, by their indices. These
+  // are shared by every file matching the same overrides.
+  resolved: Mutex<HashMap<Vec<usize>, Arc<ResolvedOverrides>>>,
+}
+
+/// The settings for a single file after applying all matching overrides. Fields are only set when
+/// at least one override changed them.
+#[derive(Default)]
+pub struct ResolvedOverrides {
+  pub skip: bool,
+  pub print_width: Option<u32>,
+  pub languages: Option<LanguageFormatters>,
+  pub formatters: Option<FormatterSpecs>,
+  pub cache: Option<Arc<FormatCache>>,
+}
+
+fn build_glob_set(globs: &[String]) -> Result<GlobSet> {
+  let mut builder = GlobSetBuilder::new();
+  for glob in globs {
+    builder.add(Glob::new(glob)?);
+  }
+  Ok(builder.build()?)
+}
+
+impl Overrides {
+  pub fn new(root: &Path, overrides: &[OverrideConfig]) -> Result<Self> {
+    let overrides = overrides
+      .iter()
+      .map(|config| {
+        Ok(CompiledOverride {
+          files: build_glob_set(&config.files)?,
+          exclude: build_glob_set(&config.exclude)?,
+          config: config.clone(),
+        })
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    Ok(Self {
+      root: root.to_path_buf(),
+      overrides,
+      resolved: Mutex::new(HashMap::new()),
+    })
+  }
+
+  /// The directory which override globs are matched relative to.
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+
+  /// Resolve the overrides for a file. The languages and formatters they extend must be the same
+  /// for every file of the run, as results are reused between files matching the same overrides.
+  pub fn resolve(
+    &self,
+    path: &Path,
+    languages: &LanguageFormatters,
+    formatters: &FormatterSpecs,
+  ) -> Arc<ResolvedOverrides> {
+    let relative_path = path.strip_prefix(&self.root).unwrap_or(path);
+
+    let matched = self
+      .overrides
+      .iter()
+      .enumerate()
+      .filter(|(_, compiled)| {
+        compiled.files.is_match(relative_path) && !compiled.exclude.is_match(relative_path)
+      })
+      .map(|(index, _)| index)
+      .collect::<Vec<_>>();
+
+    self
+      .resolved
+      .lock()
+      .unwrap()
+      .entry(matched)
+      .or_insert_with_key(|matched| Arc::new(self.merge(matched, languages, formatters)))
+      .clone()
+  }
+
+  fn merge(
+    &self,
+    matched: &[usize],
+    languages: &LanguageFormatters,
+    formatters: &FormatterSpecs,
+  ) -> ResolvedOverrides {
+    let mut resolved = ResolvedOverrides::default();
+    if matched.is_empty() {
+      return resolved;
+    }
+
+    for index in matched {
+      let config = &self.overrides[*index].config;
+      if let Some(skip) = config.skip {
+        resolved.skip = skip;
+      }
+      if let Some(print_width) = config.print_width {
+        resolved.print_width = Some(print_width);
+      }
+      if let Some(overrides) = &config.languages {
+        resolved
+          .languages
+          .get_or_insert_with(|| languages.clone())
+          .extend(overrides.clone());
+      }
+      if let Some(overrides) = &config.formatters {
+        resolved
+          .formatters
+          .get_or_insert_with(|| formatters.clone())
+          .extend(overrides.clone());
+      }
+    }
+
+    // Files with different overrides applied might format identical regions differently, so each
+    // distinct set of matching overrides gets its own cache
+    resolved.cache = Some(Arc::new(FormatCache::new()));
+
+    resolved
+  }
+}
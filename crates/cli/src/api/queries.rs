@@ -1,5 +1,5 @@
 use anyhow::Result;
-use std::{fs, path::PathBuf};
+use std::{collections::HashSet, fs, path::PathBuf};
 use tree_sitter::{Language, Query};
 
 fn read_files(paths: &[PathBuf]) -> Result<String> {
@@ -34,24 +34,67 @@ fn merge_queries(base: &str, overlay: &str) -> String {
   merged
 }
 
-fn is_extending(contents: &str) -> bool {
-  contents
-    .lines()
-    .next()
-    .map(|line| line.trim_start().starts_with(";; extends"))
-    .unwrap_or(false)
+#[derive(Debug, Default)]
+struct Modeline {
+  extends: bool,
+  inherits: Vec<String>,
 }
 
-fn read_query(queries_dirs: &[PathBuf], name: &str, filename: &str, base: &str) -> Result<String> {
+// Reads the `;; extends` and `; inherits: lang1,lang2` modelines from the leading comments of a
+// query, as understood by nvim-treesitter. Optional inherited languages, written as `(lang)`, are
+// treated like any other.
+fn parse_modeline(contents: &str) -> Modeline {
+  let mut modeline = Modeline::default();
+
+  for line in contents.lines() {
+    let Some(comment) = line.trim_start().strip_prefix(';') else {
+      break;
+    };
+    let comment = comment.trim_start_matches(';').trim();
+
+    if comment == "extends" {
+      modeline.extends = true;
+    } else if let Some(languages) = comment.strip_prefix("inherits") {
+      let languages = languages.trim_start().trim_start_matches(':');
+      modeline.inherits.extend(
+        languages
+          .split(',')
+          .map(|lang| lang.trim().trim_start_matches('(').trim_end_matches(')'))
+          .filter(|lang| !lang.is_empty())
+          .map(String::from),
+      );
+    }
+  }
+
+  modeline
+}
+
+fn read_query_inheriting(
+  queries_dirs: &[PathBuf],
+  name: &str,
+  filename: &str,
+  base: &str,
+  visited: &mut HashSet<String>,
+) -> Result<String> {
   let mut result = base.to_owned();
 
   for dir in queries_dirs {
     let path = dir.join(name).join(filename);
     if path.is_file() {
-      let contents = fs::read_to_string(&path)
+      let mut contents = fs::read_to_string(&path)
         .map_err(|e| anyhow::format_err!("Failed to read {}: {e}", path.display()))?;
 
-      if is_extending(&contents) {
+      let modeline = parse_modeline(&contents);
+      for lang in modeline.inherits.iter().rev() {
+        // Guards against languages inheriting from each other
+        if !visited.insert(lang.clone()) {
+          continue;
+        }
+        let inherited = read_query_inheriting(queries_dirs, lang, filename, "", visited)?;
+        contents = merge_queries(&inherited, &contents);
+      }
+
+      if modeline.extends {
         result = merge_queries(&result, &contents);
       } else {
         result = contents;
@@ -62,6 +105,11 @@ fn read_query(queries_dirs: &[PathBuf], name: &str, filename: &str, base: &str)
   Ok(result)
 }
 
+fn read_query(queries_dirs: &[PathBuf], name: &str, filename: &str, base: &str) -> Result<String> {
+  let mut visited = HashSet::from([name.to_string()]);
+  read_query_inheriting(queries_dirs, name, filename, base, &mut visited)
+}
+
 pub fn load_injections_query(
   lang: &Language,
   name: &str,
@@ -1,7 +1,27 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 
-pub fn offset_lines(data: &mut Vec<u8>, offset: usize) {
-  if offset == 0 {
+/// The number of columns a tab advances to the next tab stop by, unless configured otherwise.
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+// The column reached after `ch` is displayed starting at `column`.
+fn advance_column(column: usize, ch: char, tab_width: usize) -> usize {
+  if ch == '\t' && tab_width > 0 {
+    (column / tab_width + 1) * tab_width
+  } else {
+    column + 1
+  }
+}
+
+/// The display width of `text`, expanding tabs to the next multiple of `tab_width`.
+pub fn display_width(text: &str, tab_width: usize) -> usize {
+  text
+    .chars()
+    .fold(0, |column, ch| advance_column(column, ch, tab_width))
+}
+
+/// Prefix every line after the first with `indent`, leaving empty lines untouched.
+pub fn offset_lines(data: &mut Vec<u8>, indent: &str) {
+  if indent.is_empty() {
     return;
   }
 
@@ -13,15 +33,75 @@ pub fn offset_lines(data: &mut Vec<u8>, offset: usize) {
         i += 1;
         continue;
       }
-      let spaces = vec![b' '; offset];
-      data.splice(i + 1..i + 1, spaces);
-      i += offset + 1;
+      data.splice(i + 1..i + 1, indent.bytes());
+      i += indent.len() + 1;
     } else {
       i += 1;
     }
   }
 }
 
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// The byte order mark and line ending style of a document, detected before formatting so they can
+/// be restored afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Encoding {
+  pub bom: bool,
+  pub crlf: bool,
+}
+
+impl Encoding {
+  /// The line ending style is taken from the first line ending in the document.
+  pub fn detect(source: &[u8]) -> Self {
+    let crlf = source
+      .iter()
+      .position(|byte| *byte == b'\n')
+      .is_some_and(|index| index > 0 && source[index - 1] == b'\r');
+
+    Self {
+      bom: source.starts_with(BOM),
+      crlf,
+    }
+  }
+
+  /// Strip the BOM and convert CRLF line endings to LF.
+  pub fn normalize(&self, source: &[u8]) -> Vec<u8> {
+    let source = if self.bom {
+      &source[BOM.len()..]
+    } else {
+      source
+    };
+    if !self.crlf {
+      return source.to_vec();
+    }
+
+    let mut result = Vec::with_capacity(source.len());
+    for (index, byte) in source.iter().enumerate() {
+      if *byte == b'\r' && source.get(index + 1) == Some(&b'\n') {
+        continue;
+      }
+      result.push(*byte);
+    }
+    result
+  }
+
+  /// Restore the BOM and line ending style of the original document.
+  pub fn restore(&self, source: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(source.len() + BOM.len());
+    if self.bom {
+      result.extend_from_slice(BOM);
+    }
+    for (index, byte) in source.iter().enumerate() {
+      if self.crlf && *byte == b'\n' && (index == 0 || source[index - 1] != b'\r') {
+        result.push(b'\r');
+      }
+      result.push(*byte);
+    }
+    result
+  }
+}
+
 pub fn strip_trailing_newlines(data: &mut Vec<u8>) {
   while data.last() == Some(&b'\n') || data.last() == Some(&b'\r') {
     data.pop();
@@ -40,7 +120,7 @@ pub fn trailing_newlines(data: &[u8]) -> Vec<u8> {
   data[index..].to_vec()
 }
 
-pub fn column_for_byte(source: &[u8], byte_index: usize) -> usize {
+fn line_prefix(source: &[u8], byte_index: usize) -> &[u8] {
   let target = byte_index.min(source.len());
   let line_start = source[..target]
     .iter()
@@ -48,48 +128,165 @@ pub fn column_for_byte(source: &[u8], byte_index: usize) -> usize {
     .map(|index| index + 1)
     .unwrap_or(0);
 
-  target - line_start
+  &source[line_start..target]
+}
+
+/// The display column of `byte_index` within its line.
+pub fn column_for_byte(source: &[u8], byte_index: usize, tab_width: usize) -> usize {
+  display_width(
+    &String::from_utf8_lossy(line_prefix(source, byte_index)),
+    tab_width,
+  )
+}
+
+/// The indentation needed to line text up with `byte_index`, using the same characters as its line.
+/// Tabs are kept so that tab-indented documents stay tab-indented, and anything else in front of
+/// `byte_index` is replaced by a space.
+pub fn indent_for_byte(source: &[u8], byte_index: usize) -> String {
+  String::from_utf8_lossy(line_prefix(source, byte_index))
+    .chars()
+    .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+    .collect()
 }
 
-pub fn min_leading_indent(text: &str) -> usize {
-  let mut min_indent: Option<usize> = None;
+/// The indentation of the line `byte_index` is on.
+pub fn line_indent(source: &[u8], byte_index: usize) -> String {
+  let target = byte_index.min(source.len());
+  let line_start = source[..target]
+    .iter()
+    .rposition(|byte| *byte == b'\n')
+    .map_or(0, |index| index + 1);
+  let line = String::from_utf8_lossy(&source[line_start..]);
+  leading_whitespace(&line).to_string()
+}
+
+fn leading_whitespace(line: &str) -> &str {
+  let end = line
+    .find(|ch: char| ch != ' ' && ch != '\t')
+    .unwrap_or(line.len());
+  &line[..end]
+}
+
+/// The indentation shared by all non-blank lines of `text`.
+pub fn min_leading_indent(text: &str) -> String {
+  let mut min_indent: Option<&str> = None;
   for line in text.lines() {
     if line.trim().is_empty() {
       continue;
     }
-    let indent = line.chars().take_while(|ch| *ch == ' ').count();
-    min_indent = Some(min_indent.map_or(indent, |current| current.min(indent)));
+    let indent = leading_whitespace(line);
+    min_indent = Some(match min_indent {
+      None => indent,
+      Some(current) => {
+        let shared = current
+          .bytes()
+          .zip(indent.bytes())
+          .take_while(|(a, b)| a == b)
+          .count();
+        &current[..shared]
+      }
+    });
   }
 
-  min_indent.unwrap_or(0)
+  min_indent.unwrap_or_default().to_string()
 }
 
-pub fn strip_leading_indent(text: &str, indent: usize) -> String {
-  if indent == 0 {
+// Strip `indent` from the start of `line`. When the line is indented with different characters,
+// whitespace is stripped for as long as it fits within the display width of `indent`.
+fn strip_line_indent<'a>(line: &'a str, indent: &str, tab_width: usize) -> &'a str {
+  let whitespace = leading_whitespace(line);
+  let shared = whitespace
+    .bytes()
+    .zip(indent.bytes())
+    .take_while(|(a, b)| a == b)
+    .count();
+  if shared == indent.len() {
+    return &line[shared..];
+  }
+
+  let target = display_width(indent, tab_width);
+  let mut column = display_width(&whitespace[..shared], tab_width);
+  let mut end = shared;
+  for ch in whitespace[shared..].chars() {
+    let next = advance_column(column, ch, tab_width);
+    if next > target {
+      break;
+    }
+    column = next;
+    end += 1;
+  }
+
+  &line[end..]
+}
+
+pub fn strip_leading_indent(text: &str, indent: &str, tab_width: usize) -> String {
+  if indent.is_empty() {
     return text.to_string();
   }
 
   let mut result = String::with_capacity(text.len());
   for segment in text.split_inclusive('\n') {
-    let (line, newline) = if segment.ends_with('\n') {
-      (&segment[..segment.len() - 1], "\n")
-    } else {
-      (segment, "")
-    };
-    let leading_spaces = line.chars().take_while(|ch| *ch == ' ').count();
-    let trim_count = indent.min(leading_spaces);
-    let trimmed = if trim_count > 0 {
-      &line[trim_count..]
-    } else {
-      line
+    let (line, newline) = match segment.strip_suffix('\n') {
+      Some(line) => (line, "\n"),
+      None => (segment, ""),
     };
-    result.push_str(trimmed);
+    result.push_str(strip_line_indent(line, indent, tab_width));
     result.push_str(newline);
   }
 
   result
 }
 
+/// Remove `prefix`, such as `/// ` or `> `, from the start of each line of `text` which has it. Lines
+/// starting with the prefix without its trailing whitespace, like a bare `///`, lose that instead.
+pub fn strip_line_prefix(text: &str, prefix: &str) -> String {
+  let trimmed_prefix = prefix.trim_end();
+  if trimmed_prefix.is_empty() {
+    return text.to_string();
+  }
+
+  let mut result = String::with_capacity(text.len());
+  for line in text.split_inclusive('\n') {
+    let stripped = line
+      .strip_prefix(prefix)
+      .or_else(|| line.strip_prefix(trimmed_prefix))
+      .unwrap_or(line);
+    result.push_str(stripped);
+  }
+  result
+}
+
+/// Add `prefix` back to the start of every line of `text`, the counterpart of [`strip_line_prefix`].
+/// Empty lines get the prefix without its trailing whitespace.
+pub fn add_line_prefix(text: &str, prefix: &str) -> String {
+  let trimmed_prefix = prefix.trim_end();
+  let mut result = String::with_capacity(text.len());
+  for segment in text.split_inclusive('\n') {
+    let (line, newline) = match segment.strip_suffix('\n') {
+      Some(line) => (line, "\n"),
+      None => (segment, ""),
+    };
+    if line.trim().is_empty() {
+      result.push_str(trimmed_prefix);
+    } else {
+      result.push_str(prefix);
+      result.push_str(line);
+    }
+    result.push_str(newline);
+  }
+  result
+}
+
+/// Join the lines of `text` into one, separated by single spaces. Blank lines are dropped.
+pub fn join_lines(text: &str) -> String {
+  text
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty())
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
 pub fn sort_escape_chars(escape_chars: &HashSet<String>) -> Vec<String> {
   let mut chars: Vec<String> = escape_chars.iter().cloned().collect();
   chars.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
@@ -178,3 +375,362 @@ pub fn escape_text(text: &str, escape_chars: &[String]) -> String {
 
   result
 }
+
+/// The escaping scheme of an injected region within its host document, chosen with
+/// `#escape-style!`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum EscapeStyle {
+  /// Characters are prefixed with a backslash, as in `\"`.
+  #[default]
+  Backslash,
+  /// Characters are doubled, as in SQL's `''` or CSV's `""`.
+  Doubled,
+  /// JSON string escapes, including `\n` and `\uXXXX`.
+  Json,
+  /// HTML character references such as `&quot;` and `&#39;`.
+  HtmlEntities,
+  /// URL percent-encoding, as in `%20`.
+  Percent,
+}
+
+impl EscapeStyle {
+  pub fn from_name(name: &str) -> Option<Self> {
+    let style = match name {
+      "backslash" => Self::Backslash,
+      "doubled" => Self::Doubled,
+      "json" => Self::Json,
+      "html-entities" => Self::HtmlEntities,
+      "percent" => Self::Percent,
+      _ => return None,
+    };
+    Some(style)
+  }
+}
+
+/// How the text of an injected region is escaped within its host document: a scheme, and the
+/// characters it applies to.
+///
+/// Without characters, `doubled` and `html-entities` apply to `"`, and `percent` encodes everything
+/// `encodeURIComponent` would. `json` always escapes what JSON requires.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Escaping {
+  style: EscapeStyle,
+  chars: Vec<String>,
+}
+
+/// The original spelling of the characters of a region which its escaping scheme can spell in more
+/// than one way, in the order they appear. Escaping the formatted region reuses them, so that text
+/// the formatter didn't change is written back as it was.
+#[derive(Debug, Clone, Default)]
+pub struct Spellings {
+  spellings: HashMap<char, VecDeque<String>>,
+}
+
+impl Spellings {
+  /// Append the spellings of a later part of the same region.
+  pub fn extend(&mut self, other: Spellings) {
+    for (ch, spellings) in other.spellings {
+      self.spellings.entry(ch).or_default().extend(spellings);
+    }
+  }
+}
+
+impl Escaping {
+  pub fn new(style: EscapeStyle, chars: &HashSet<String>) -> Self {
+    let mut chars = sort_escape_chars(chars);
+    if chars.is_empty() && matches!(style, EscapeStyle::Doubled | EscapeStyle::HtmlEntities) {
+      chars.push("\"".to_string());
+    }
+    Self { style, chars }
+  }
+
+  /// Whether text is left as it is.
+  pub fn is_none(&self) -> bool {
+    self.style == EscapeStyle::Backslash && self.chars.is_empty()
+  }
+
+  pub fn unescape(&self, text: &str) -> String {
+    self.unescape_spelled(text).0
+  }
+
+  /// Unescape `text`, recording how its characters were spelled so that
+  /// [`Escaping::escape_spelled`] can spell them the same way again. Only `json`, `html-entities`
+  /// and `percent` have more than one spelling for a character.
+  pub fn unescape_spelled(&self, text: &str) -> (String, Spellings) {
+    let decode: fn(&str) -> Option<(char, usize)> = match self.style {
+      _ if self.is_none() => return (text.to_string(), Spellings::default()),
+      EscapeStyle::Backslash => return (unescape_text(text, &self.chars), Spellings::default()),
+      EscapeStyle::Doubled => return (unescape_doubled(text, &self.chars), Spellings::default()),
+      EscapeStyle::Json => decode_json,
+      EscapeStyle::HtmlEntities => decode_html_entity,
+      EscapeStyle::Percent => decode_percent,
+    };
+
+    let mut decoded = Vec::new();
+    let mut rest = text;
+    while let Some(ch) = rest.chars().next() {
+      let (ch, len) = decode(rest).unwrap_or((ch, ch.len_utf8()));
+      decoded.push((ch, &rest[..len]));
+      rest = &rest[len..];
+    }
+
+    // Every occurrence of a character is recorded once any of them is spelled as an escape, so that
+    // their spellings stay in order.
+    let respelled: HashSet<char> = decoded
+      .iter()
+      .filter(|(ch, spelling)| spelling.chars().ne([*ch]))
+      .map(|(ch, _)| *ch)
+      .collect();
+    let mut spellings = Spellings::default();
+    for &(ch, spelling) in &decoded {
+      if respelled.contains(&ch) || self.escape_char(ch).is_some() {
+        spellings
+          .spellings
+          .entry(ch)
+          .or_default()
+          .push_back(spelling.to_string());
+      }
+    }
+
+    (decoded.iter().map(|(ch, _)| ch).collect(), spellings)
+  }
+
+  pub fn escape(&self, text: &str) -> String {
+    self.escape_spelled(text, &mut Spellings::default())
+  }
+
+  /// Escape `text`, taking the spelling of each character from `spellings` in order while there are
+  /// any left. They're only used if the result unescapes back to `text`, as an unescaped `&` or `%`
+  /// may start an escape once the text around it changes.
+  pub fn escape_spelled(&self, text: &str, spellings: &mut Spellings) -> String {
+    match self.style {
+      _ if self.is_none() => return text.to_string(),
+      EscapeStyle::Backslash => return escape_text(text, &self.chars),
+      EscapeStyle::Doubled => return escape_doubled(text, &self.chars),
+      EscapeStyle::Json | EscapeStyle::HtmlEntities | EscapeStyle::Percent => {}
+    }
+
+    let mut respelled = false;
+    let mut result = String::with_capacity(text.len());
+    for ch in text.chars() {
+      if let Some(spelling) = spellings
+        .spellings
+        .get_mut(&ch)
+        .and_then(VecDeque::pop_front)
+      {
+        respelled = true;
+        result.push_str(&spelling);
+        continue;
+      }
+      match self.escape_char(ch) {
+        Some(escaped) => result.push_str(&escaped),
+        None => result.push(ch),
+      }
+    }
+
+    if respelled && self.unescape(&result) != text {
+      return self.escape(text);
+    }
+    result
+  }
+
+  // How the scheme spells `ch` when it wasn't spelled otherwise, or `None` when it's kept as it is.
+  fn escape_char(&self, ch: char) -> Option<String> {
+    match self.style {
+      EscapeStyle::Json => escape_json_char(ch),
+      EscapeStyle::HtmlEntities => escape_html_entity(ch, &self.chars),
+      EscapeStyle::Percent => escape_percent_char(ch, &self.chars),
+      EscapeStyle::Backslash | EscapeStyle::Doubled => None,
+    }
+  }
+}
+
+fn unescape_doubled(text: &str, chars: &[String]) -> String {
+  let mut result = String::with_capacity(text.len());
+  let mut rest = text;
+  'outer: while let Some(ch) = rest.chars().next() {
+    for escape in chars {
+      if rest.len() >= escape.len() * 2
+        && rest.starts_with(escape.as_str())
+        && rest[escape.len()..].starts_with(escape.as_str())
+      {
+        result.push_str(escape);
+        rest = &rest[escape.len() * 2..];
+        continue 'outer;
+      }
+    }
+    result.push(ch);
+    rest = &rest[ch.len_utf8()..];
+  }
+  result
+}
+
+fn escape_doubled(text: &str, chars: &[String]) -> String {
+  let mut result = String::with_capacity(text.len());
+  let mut rest = text;
+  'outer: while let Some(ch) = rest.chars().next() {
+    for escape in chars {
+      if rest.starts_with(escape.as_str()) {
+        result.push_str(escape);
+        result.push_str(escape);
+        rest = &rest[escape.len()..];
+        continue 'outer;
+      }
+    }
+    result.push(ch);
+    rest = &rest[ch.len_utf8()..];
+  }
+  result
+}
+
+fn parse_json_unit(text: &str) -> Option<u32> {
+  let hex = text.get(..4)?;
+  if !hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+    return None;
+  }
+  u32::from_str_radix(hex, 16).ok()
+}
+
+// Decode the `\uXXXX` escape at the start of `text`, which follows the backslash and `u`, returning
+// the character and the length of the escape. Surrogate pairs span two escapes.
+fn unescape_json_unicode(text: &str) -> Option<(char, usize)> {
+  let unit = parse_json_unit(text)?;
+  if (0xD800..0xDC00).contains(&unit) {
+    let low = text
+      .get(4..)?
+      .strip_prefix("\\u")
+      .and_then(parse_json_unit)?;
+    if !(0xDC00..0xE000).contains(&low) {
+      return None;
+    }
+    let code = 0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00);
+    return char::from_u32(code).map(|ch| (ch, 10));
+  }
+  char::from_u32(unit).map(|ch| (ch, 4))
+}
+
+// Decode the JSON string escape at the start of `text`, returning the character and the length of
+// the escape. Invalid escapes are kept as they are.
+fn decode_json(text: &str) -> Option<(char, usize)> {
+  let rest = text.strip_prefix('\\')?;
+  let ch = match rest.chars().next()? {
+    '"' => '"',
+    '\\' => '\\',
+    '/' => '/',
+    'b' => '\u{8}',
+    'f' => '\u{c}',
+    'n' => '\n',
+    'r' => '\r',
+    't' => '\t',
+    'u' => return unescape_json_unicode(&rest[1..]).map(|(ch, len)| (ch, 2 + len)),
+    _ => return None,
+  };
+  Some((ch, 2))
+}
+
+// The escape JSON requires for `ch`. Optional escapes such as `\/` are only kept where the region
+// already used them.
+fn escape_json_char(ch: char) -> Option<String> {
+  let escaped = match ch {
+    '"' => "\\\"",
+    '\\' => "\\\\",
+    '\n' => "\\n",
+    '\r' => "\\r",
+    '\t' => "\\t",
+    '\u{8}' => "\\b",
+    '\u{c}' => "\\f",
+    ch if (ch as u32) < 0x20 => return Some(format!("\\u{:04x}", ch as u32)),
+    _ => return None,
+  };
+  Some(escaped.to_string())
+}
+
+fn html_entity(name: &str) -> Option<char> {
+  let ch = match name {
+    "amp" => '&',
+    "lt" => '<',
+    "gt" => '>',
+    "quot" => '"',
+    "apos" => '\'',
+    _ => {
+      let code = name.strip_prefix('#')?;
+      let code = match code.strip_prefix(['x', 'X']) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => code.parse().ok()?,
+      };
+      return char::from_u32(code);
+    }
+  };
+  Some(ch)
+}
+
+// Decode the basic named character reference or numeric one at the start of `text`, returning the
+// character and the length of the reference. Anything else is kept as it is.
+fn decode_html_entity(text: &str) -> Option<(char, usize)> {
+  let rest = text.strip_prefix('&')?;
+  let end = rest.find(';')?;
+  html_entity(&rest[..end]).map(|ch| (ch, end + 2))
+}
+
+fn escape_html_entity(ch: char, chars: &[String]) -> Option<String> {
+  let escaped = match ch {
+    '&' => "&amp;",
+    ch if chars.iter().any(|escape| escape.starts_with(ch)) => match ch {
+      '<' => "&lt;",
+      '>' => "&gt;",
+      '"' => "&quot;",
+      ch => return Some(format!("&#{};", ch as u32)),
+    },
+    _ => return None,
+  };
+  Some(escaped.to_string())
+}
+
+// Decode the `%XX` sequence of one utf-8 character at the start of `text`, returning the character
+// and the length of the sequence. Sequences which aren't valid utf-8 are kept as they are.
+fn decode_percent(text: &str) -> Option<(char, usize)> {
+  let mut bytes = Vec::with_capacity(4);
+  let mut rest = text;
+  loop {
+    let hex = rest.strip_prefix('%')?.get(..2)?;
+    if !hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+      return None;
+    }
+    bytes.push(u8::from_str_radix(hex, 16).ok()?);
+    rest = &rest[3..];
+    match std::str::from_utf8(&bytes) {
+      Ok(decoded) => {
+        return decoded
+          .chars()
+          .next()
+          .map(|ch| (ch, text.len() - rest.len()));
+      }
+      Err(error) if error.error_len().is_none() => continue,
+      Err(_) => return None,
+    }
+  }
+}
+
+// Characters which `encodeURIComponent` leaves as they are.
+fn is_uri_unreserved(ch: char) -> bool {
+  ch.is_ascii_alphanumeric() || "-_.!~*'()".contains(ch)
+}
+
+fn escape_percent_char(ch: char, chars: &[String]) -> Option<String> {
+  let encode = ch == '%'
+    || if chars.is_empty() {
+      !is_uri_unreserved(ch)
+    } else {
+      chars.iter().any(|escape| escape.starts_with(ch))
+    };
+  if !encode {
+    return None;
+  }
+  let mut buffer = [0; 4];
+  let escaped = ch
+    .encode_utf8(&mut buffer)
+    .bytes()
+    .map(|byte| format!("%{byte:02X}"))
+    .collect();
+  Some(escaped)
+}
@@ -1,13 +1,21 @@
 use anyhow::{Context, Result};
-use std::{fs, io::Read, path::PathBuf, process::exit, time::Instant};
+use std::{
+  fs,
+  io::{Read, Write},
+  path::PathBuf,
+  process::exit,
+  sync::Arc,
+  time::Instant,
+};
 
 use crate::{
   api::{
     self,
-    format::{self, FormatContext, FormatOpts},
+    format::{self, FormatContext, FormatOpts, FormatWarnings},
+    overrides::Overrides,
   },
   cli::GlobalOpts,
-  config::{self, LoadOpts},
+  config::{self, FormatOrder, LoadOpts, OnError, SafetyCheck},
   wasm::formatter::WasmFormatter,
 };
 
@@ -39,6 +47,43 @@ pub struct FormatArgs {
   )]
   skip_root: bool,
 
+  /// The path of the file being formatted when reading source from stdin. This is used to resolve
+  /// path-scoped overrides and to name temp files, the file itself is not read.
+  #[arg(long)]
+  stdin_filepath: Option<PathBuf>,
+
+  /// Format the result of formatting a second time, and fail with a diff if it changes. Useful for
+  /// finding formatter chains which don't converge.
+  #[arg(
+    long,
+    default_value_t = false,
+    num_args = 0..=1,
+    default_missing_value = "true",
+    value_parser = clap::builder::BoolValueParser::new()
+  )]
+  verify_idempotent: bool,
+
+  /// What to do when an injected region fails to format. With `skip` the region is left unchanged
+  /// and the failure is reported as a warning. Overrides `on_error` from the config.
+  #[arg(long, value_enum)]
+  on_error: Option<OnError>,
+
+  /// Check that formatted output doesn't break the syntax tree of the document before accepting
+  /// it. Output which fails the check is rejected and reported as a warning. Overrides
+  /// `safety_check` from the config.
+  #[arg(long, value_enum)]
+  safety_check: Option<SafetyCheck>,
+
+  /// Format the document and its injected regions in turn up to this many times, stopping once the
+  /// result stops changing. Overrides `max_passes` from the config.
+  #[arg(long)]
+  max_passes: Option<usize>,
+
+  /// Whether the root document or its injected regions are formatted first. Overrides
+  /// `format_order` from the config.
+  #[arg(long, value_enum)]
+  format_order: Option<FormatOrder>,
+
   /// The current working directory. Only used when formatting files.
   #[arg(long, short('d'))]
   dir: Option<PathBuf>,
@@ -71,6 +116,12 @@ pub struct FormatArgs {
   include_glob: Option<String>,
 }
 
+fn report_warnings(warnings: &FormatWarnings) {
+  for warning in warnings.take() {
+    log::warn!("{warning}");
+  }
+}
+
 fn format_stdin(args: &FormatArgs, context: &FormatContext) -> Result<()> {
   let input = {
     let mut buf = Vec::new();
@@ -78,42 +129,48 @@ fn format_stdin(args: &FormatArgs, context: &FormatContext) -> Result<()> {
     buf
   };
 
+  let opts = FormatOpts {
+    printwidth: args.print_width,
+    language: &args.lang,
+    path: None,
+    indent_width: None,
+    variables: &[],
+  };
+
   let start = Instant::now();
-  let result = format::format(
-    &input,
-    &FormatOpts {
-      printwidth: args.print_width,
-      language: &args.lang,
-    },
-    !args.skip_root,
-    true,
-    context,
-  )?;
+  let result = match &args.stdin_filepath {
+    Some(path) => format::format_document(&input, path, &opts, !args.skip_root, context)?,
+    None => format::format(&input, &opts, !args.skip_root, true, context)?,
+  };
+  context.daemons.shutdown();
   log::debug!(
     "Format time total: {:?}",
     Instant::now().duration_since(start)
   );
 
-  print!("{}", String::from_utf8(result).unwrap());
+  std::io::stdout().write_all(&result)?;
+  report_warnings(&context.warnings);
 
   Ok(())
 }
 
 fn format_files(args: &FormatArgs, context: &FormatContext) -> Result<()> {
-  let cwd = std::env::current_dir()?;
-
   let paths = format::format_files(
-    &args.dir.clone().unwrap_or(cwd),
+    &format_dir(args)?,
     &args.include_glob.clone().unwrap(),
     args.exclude.clone(),
     !args.check,
     &FormatOpts {
       printwidth: args.print_width,
       language: &args.lang,
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     args.skip_root,
     context,
   )?;
+  report_warnings(&context.warnings);
 
   if args.check {
     if !paths.is_empty() {
@@ -127,6 +184,11 @@ fn format_files(args: &FormatArgs, context: &FormatContext) -> Result<()> {
   Ok(())
 }
 
+fn format_dir(args: &FormatArgs) -> Result<PathBuf> {
+  let cwd = std::env::current_dir()?;
+  Ok(args.dir.clone().unwrap_or(cwd))
+}
+
 pub fn handle(args: FormatArgs, global: GlobalOpts) -> Result<()> {
   let cwd = std::env::current_dir()?;
   let config = config::load(LoadOpts {
@@ -160,11 +222,29 @@ pub fn handle(args: FormatArgs, global: GlobalOpts) -> Result<()> {
     Instant::now().duration_since(start)
   );
 
+  let overrides_root = if args.include_glob.is_some() {
+    format_dir(&args)?
+  } else {
+    cwd
+  };
+  let overrides = Overrides::new(&overrides_root, &config.overrides)
+    .context("Failed to compile override globs")?;
+
   let context = FormatContext {
-    grammars: &grammars,
-    languages: &config.languages,
-    formatters: &config.formatters,
-    wasm_formatter: &wasm_formatter,
+    aliases: &config.aliases,
+    overrides: Arc::new(overrides),
+    on_error: args.on_error.unwrap_or(config.on_error),
+    verify_idempotent: args.verify_idempotent,
+    safety_check: args.safety_check.unwrap_or(config.safety_check),
+    max_injection_depth: config.max_injection_depth,
+    max_passes: args.max_passes.unwrap_or(config.max_passes).max(1),
+    format_order: args.format_order.unwrap_or(config.format_order),
+    ..FormatContext::new(
+      &grammars,
+      &config.languages,
+      &config.formatters,
+      &wasm_formatter,
+    )
   };
 
   if args.include_glob.is_some() {
@@ -3,6 +3,7 @@ use std::{
   collections::HashMap,
   hash::Hash,
   path::{Path, PathBuf},
+  sync::Arc,
 };
 use url::Url;
 
@@ -32,12 +33,86 @@ impl GrammarSpec {
   }
 }
 
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TempFileLocation {
+  /// The system temp directory.
+  #[default]
+  System,
+  /// The directory containing the file being formatted.
+  Adjacent,
+}
+
+/// Where temp files should be written when a formatter is configured with `stdin = false`.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum TempFileSpec {
+  Location(TempFileLocation),
+  Sandbox { dir: PathBuf },
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FormatterProtocol {
+  /// A long-lived process receiving JSON-RPC 2.0 `format` requests over stdin and replying over
+  /// stdout. Messages are framed with a `Content-Length` header, the same as LSP.
+  Jsonrpc,
+}
+
 #[derive(serde::Deserialize, Debug, Clone, PartialEq)]
 pub struct FormatterSpec {
   pub cmd: String,
   pub args: Vec<String>,
   pub stdin: Option<bool>,
   pub fail_on_stderr: Option<bool>,
+  pub temp_file: Option<TempFileSpec>,
+  pub protocol: Option<FormatterProtocol>,
+}
+
+impl FormatterSpec {
+  /// Whether the formatter can see where the file being formatted is, and so might pick up config
+  /// files next to it.
+  pub fn depends_on_path(&self) -> bool {
+    self.protocol == Some(FormatterProtocol::Jsonrpc)
+      || self.temp_file == Some(TempFileSpec::Location(TempFileLocation::Adjacent))
+  }
+}
+
+/// How thoroughly formatted output is checked before it replaces the original source.
+#[derive(serde::Deserialize, clap::ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SafetyCheck {
+  #[default]
+  Off,
+  /// Reject output which has more `ERROR` or `MISSING` nodes than the input.
+  Syntax,
+  /// Additionally reject output whose syntax tree differs from the input, ignoring whitespace and
+  /// the contents of injected regions.
+  Strict,
+}
+
+/// What to do when an injected region fails to format.
+#[derive(serde::Deserialize, clap::ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+  /// Fail formatting the whole document.
+  #[default]
+  Fail,
+  /// Leave the region unchanged and report the failure as a warning.
+  Skip,
+}
+
+/// Which is formatted first when a document is formatted in several passes, see `max_passes`.
+#[derive(serde::Deserialize, clap::ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum FormatOrder {
+  /// Run the formatters of the root document, then format its injected regions.
+  #[default]
+  RootFirst,
+  /// Format the injected regions before the first run of the root formatters, so that they see
+  /// the final width of each region. This is an extra pass over the injected regions only, which
+  /// doesn't count against `max_passes`.
+  InjectionsFirst,
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -64,10 +139,85 @@ fn default_resource() -> bool {
   true
 }
 
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum DepthCondition {
+  Exact(usize),
+  Range {
+    min: Option<usize>,
+    max: Option<usize>,
+  },
+}
+
+impl DepthCondition {
+  pub fn matches(&self, depth: usize) -> bool {
+    match self {
+      Self::Exact(expected) => depth == *expected,
+      Self::Range { min, max } => {
+        min.is_none_or(|min| depth >= min) && max.is_none_or(|max| depth <= max)
+      }
+    }
+  }
+}
+
+/// A glob matched against file paths, compiled when the config is loaded so that invalid globs are
+/// reported before anything is formatted.
+#[derive(Debug, Clone)]
+pub struct PathGlob {
+  glob: String,
+  matcher: Arc<globset::GlobMatcher>,
+}
+
+impl PathGlob {
+  pub fn new(glob: &str) -> Result<Self> {
+    let matcher = globset::Glob::new(glob)
+      .with_context(|| format!("Invalid path glob {glob:?}"))?
+      .compile_matcher();
+    Ok(Self {
+      glob: glob.to_string(),
+      matcher: Arc::new(matcher),
+    })
+  }
+
+  pub fn is_match(&self, path: &Path) -> bool {
+    self.matcher.is_match(path)
+  }
+}
+
+impl PartialEq for PathGlob {
+  fn eq(&self, other: &Self) -> bool {
+    self.glob == other.glob
+  }
+}
+
+impl<'de> serde::Deserialize<'de> for PathGlob {
+  fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let glob = String::deserialize(deserializer)?;
+    Self::new(&glob).map_err(|err| serde::de::Error::custom(format!("{err:#}")))
+  }
+}
+
+/// Restricts a formatter to regions found in a specific context. All specified conditions must
+/// match for the formatter to run.
+#[derive(serde::Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct FormatCondition {
+  /// The language of the document containing the region.
+  pub parent_language: Option<String>,
+  /// How many injections deep the region is. The document root has a depth of 0.
+  pub depth: Option<DepthCondition>,
+  /// The name of a capture in the injection query pattern which matched the region.
+  pub capture: Option<String>,
+  /// A glob matched against the path of the file being formatted, relative to the directory being
+  /// formatted the same as `[[overrides]]`.
+  pub path: Option<PathGlob>,
+}
+
 #[derive(serde::Deserialize, Debug, Clone, PartialEq)]
 #[serde(untagged)]
 pub enum LanguageFormatSpec {
   String(String),
+  /// A list of alternatives of which only the first available formatter is run.
+  Alternatives(Vec<LanguageFormatSpec>),
   Table {
     formatter: String,
 
@@ -75,29 +225,62 @@ pub enum LanguageFormatSpec {
     run_in_root: bool,
     #[serde(default = "default_resource")]
     run_in_injections: bool,
+    /// When set, a formatter which is not available is skipped with a warning instead of failing.
+    #[serde(default)]
+    optional: bool,
+    when: Option<FormatCondition>,
   },
 }
 impl LanguageFormatSpec {
   pub fn formatter(&self) -> &str {
     match self {
       Self::String(formatter) => formatter,
+      Self::Alternatives(alternatives) => alternatives
+        .first()
+        .map(|alternative| alternative.formatter())
+        .unwrap_or_default(),
       Self::Table { formatter, .. } => formatter,
     }
   }
   pub fn run_in_root(&self) -> bool {
     match self {
       Self::String(_) => true,
+      Self::Alternatives(alternatives) => alternatives.iter().any(Self::run_in_root),
       Self::Table { run_in_root, .. } => *run_in_root,
     }
   }
   pub fn run_in_injections(&self) -> bool {
     match self {
       Self::String(_) => true,
+      Self::Alternatives(alternatives) => alternatives.iter().any(Self::run_in_injections),
       Self::Table {
         run_in_injections, ..
       } => *run_in_injections,
     }
   }
+  pub fn optional(&self) -> bool {
+    match self {
+      Self::String(_) => false,
+      Self::Alternatives(alternatives) => alternatives.iter().all(Self::optional),
+      Self::Table { optional, .. } => *optional,
+    }
+  }
+  pub fn condition(&self) -> Option<&FormatCondition> {
+    match self {
+      Self::Table { when, .. } => when.as_ref(),
+      _ => None,
+    }
+  }
+  /// The individual formatters this spec could select between, in order of preference.
+  pub fn candidates(&self) -> Vec<&LanguageFormatSpec> {
+    match self {
+      Self::Alternatives(alternatives) => alternatives
+        .iter()
+        .flat_map(|alternative| alternative.candidates())
+        .collect(),
+      _ => vec![self],
+    }
+  }
 }
 
 impl From<String> for LanguageFormatSpec {
@@ -113,7 +296,162 @@ impl From<&str> for LanguageFormatSpec {
 }
 
 pub type LanguageFormatSpecs = Vec<LanguageFormatSpec>;
-pub type LanguageFormatters = HashMap<String, LanguageFormatSpecs>;
+
+/// Layout settings applied whenever a root document or injected region of a language is formatted.
+#[derive(serde::Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct LanguageSettings {
+  /// The column at which text should wrap. Replaces the print width inherited from the parent
+  /// document, and is reduced by the column the region starts at.
+  pub print_width: Option<u32>,
+  pub min_print_width: Option<u32>,
+  pub max_print_width: Option<u32>,
+  /// Passed to formatters as `$indentwidth`.
+  pub indent_width: Option<u32>,
+  /// How many columns a tab counts for when regions are injected into a document of this language
+  /// with tab indentation. Defaults to 4.
+  pub tab_width: Option<u32>,
+  /// Regexes matching template placeholders such as `${table}` or `:name` in regions of this
+  /// language. Matches are replaced with plain identifiers while the region is formatted.
+  #[serde(default)]
+  pub placeholders: Vec<String>,
+}
+
+impl LanguageSettings {
+  /// The print width for source of this language starting at `column`, given the width it would
+  /// otherwise inherit.
+  pub fn print_width(&self, inherited: u32, column: usize) -> u32 {
+    let width = match self.print_width {
+      Some(print_width) => print_width.saturating_sub(column as u32),
+      None => inherited,
+    };
+    let width = self.max_print_width.map_or(width, |max| width.min(max));
+    self
+      .min_print_width
+      .map_or(width, |min| width.max(min))
+      .max(1)
+  }
+}
+
+/// Either a list of formatters, or a table of formatters along with [`LanguageSettings`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum LanguageSpec {
+  Formatters(LanguageFormatSpecs),
+  Table {
+    formatters: LanguageFormatSpecs,
+    settings: LanguageSettings,
+  },
+}
+
+// The table form of a language spec. The settings are spelled out rather than flattened so that
+// unknown keys, such as misspelled settings, are rejected.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LanguageTable {
+  #[serde(default)]
+  formatters: LanguageFormatSpecs,
+  print_width: Option<u32>,
+  min_print_width: Option<u32>,
+  max_print_width: Option<u32>,
+  indent_width: Option<u32>,
+  tab_width: Option<u32>,
+  #[serde(default)]
+  placeholders: Vec<String>,
+}
+
+impl<'de> serde::Deserialize<'de> for LanguageSpec {
+  fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    struct LanguageSpecVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for LanguageSpecVisitor {
+      type Value = LanguageSpec;
+
+      fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a list of formatters or a table of language settings")
+      }
+
+      fn visit_seq<A: serde::de::SeqAccess<'de>>(self, seq: A) -> Result<LanguageSpec, A::Error> {
+        let deserializer = serde::de::value::SeqAccessDeserializer::new(seq);
+        serde::Deserialize::deserialize(deserializer).map(LanguageSpec::Formatters)
+      }
+
+      fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<LanguageSpec, A::Error> {
+        let deserializer = serde::de::value::MapAccessDeserializer::new(map);
+        let table = <LanguageTable as serde::Deserialize>::deserialize(deserializer)?;
+        Ok(LanguageSpec::Table {
+          formatters: table.formatters,
+          settings: LanguageSettings {
+            print_width: table.print_width,
+            min_print_width: table.min_print_width,
+            max_print_width: table.max_print_width,
+            indent_width: table.indent_width,
+            tab_width: table.tab_width,
+            placeholders: table.placeholders,
+          },
+        })
+      }
+    }
+
+    deserializer.deserialize_any(LanguageSpecVisitor)
+  }
+}
+
+impl LanguageSpec {
+  pub fn formatters(&self) -> &[LanguageFormatSpec] {
+    match self {
+      Self::Formatters(formatters) => formatters,
+      Self::Table { formatters, .. } => formatters,
+    }
+  }
+
+  pub fn settings(&self) -> LanguageSettings {
+    match self {
+      Self::Formatters(_) => LanguageSettings::default(),
+      Self::Table { settings, .. } => settings.clone(),
+    }
+  }
+}
+
+impl From<LanguageFormatSpecs> for LanguageSpec {
+  fn from(value: LanguageFormatSpecs) -> Self {
+    LanguageSpec::Formatters(value)
+  }
+}
+
+pub type LanguageFormatters = HashMap<String, LanguageSpec>;
+
+/// Maps the language names used by injections, such as `postgres` or `py`, to the names of
+/// languages.
+pub type LanguageAliases = HashMap<String, String>;
+
+/// Settings which only apply to files matching the `files` globs. Globs are matched against paths
+/// relative to the directory being formatted. When several overrides match a file they are applied
+/// in the order they are defined.
+#[derive(serde::Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct OverrideConfig {
+  pub files: Vec<String>,
+  #[serde(default)]
+  pub exclude: Vec<String>,
+
+  /// Leave matching files untouched.
+  pub skip: Option<bool>,
+  pub print_width: Option<u32>,
+
+  pub languages: Option<LanguageFormatters>,
+  pub formatters: Option<FormatterSpecs>,
+}
+
+impl OverrideConfig {
+  fn absolutize_paths(mut self, base_dir: &Path) -> Self {
+    self.formatters = self
+      .formatters
+      .map(|formatters| absolutize_formatters(formatters, base_dir));
+    self
+  }
+}
+
+/// How deeply injected regions may be nested unless configured otherwise. Regions nested deeper
+/// than this fail to format.
+pub const DEFAULT_MAX_INJECTION_DEPTH: usize = 16;
 
 /// Profile-specific configuration overrides.
 /// Has the same fields as ConfigFile (except profiles) to allow full override capability.
@@ -127,8 +465,16 @@ pub struct ProfileConfig {
 
   pub grammars: Option<GrammarSpecs>,
   pub languages: Option<LanguageFormatters>,
+  pub aliases: Option<LanguageAliases>,
   pub formatters: Option<FormatterSpecs>,
   pub plugins: Option<PluginSpecs>,
+  pub overrides: Option<Vec<OverrideConfig>>,
+
+  pub on_error: Option<OnError>,
+  pub safety_check: Option<SafetyCheck>,
+  pub max_injection_depth: Option<usize>,
+  pub max_passes: Option<usize>,
+  pub format_order: Option<FormatOrder>,
 }
 
 impl ProfileConfig {
@@ -145,6 +491,12 @@ impl ProfileConfig {
     self.grammar_build_dir = self
       .grammar_build_dir
       .map(|path| absolutize_path(path, base_dir));
+    self.formatters = self
+      .formatters
+      .map(|formatters| absolutize_formatters(formatters, base_dir));
+    self.overrides = self
+      .overrides
+      .map(|overrides| absolutize_overrides(overrides, base_dir));
 
     self
   }
@@ -162,8 +514,20 @@ pub struct ConfigFile {
 
   pub grammars: Option<GrammarSpecs>,
   pub languages: Option<LanguageFormatters>,
+  pub aliases: Option<LanguageAliases>,
   pub formatters: Option<FormatterSpecs>,
   pub plugins: Option<PluginSpecs>,
+  pub overrides: Option<Vec<OverrideConfig>>,
+
+  pub on_error: Option<OnError>,
+  pub safety_check: Option<SafetyCheck>,
+  /// How deeply injected regions may be nested within each other, see
+  /// [`DEFAULT_MAX_INJECTION_DEPTH`].
+  pub max_injection_depth: Option<usize>,
+  /// How many times the root formatters and injected regions may be formatted in turn, stopping
+  /// early once the output stops changing. Defaults to a single pass.
+  pub max_passes: Option<usize>,
+  pub format_order: Option<FormatOrder>,
 
   pub profiles: Option<HashMap<String, ProfileConfig>>,
 }
@@ -181,8 +545,16 @@ pub struct Config {
 
   pub grammars: GrammarSpecs,
   pub languages: LanguageFormatters,
+  pub aliases: LanguageAliases,
   pub formatters: FormatterSpecs,
   pub plugins: PluginSpecs,
+  pub overrides: Vec<OverrideConfig>,
+
+  pub on_error: OnError,
+  pub safety_check: SafetyCheck,
+  pub max_injection_depth: usize,
+  pub max_passes: usize,
+  pub format_order: FormatOrder,
 }
 
 fn absolutize_vec(paths: Vec<PathBuf>, base_dir: &Path) -> Vec<PathBuf> {
@@ -200,6 +572,27 @@ fn absolutize_path(path: PathBuf, base_dir: &Path) -> PathBuf {
   }
 }
 
+fn absolutize_formatters(formatters: FormatterSpecs, base_dir: &Path) -> FormatterSpecs {
+  formatters
+    .into_iter()
+    .map(|(name, mut spec)| {
+      if let Some(TempFileSpec::Sandbox { dir }) = spec.temp_file {
+        spec.temp_file = Some(TempFileSpec::Sandbox {
+          dir: absolutize_path(dir, base_dir),
+        });
+      }
+      (name, spec)
+    })
+    .collect()
+}
+
+fn absolutize_overrides(overrides: Vec<OverrideConfig>, base_dir: &Path) -> Vec<OverrideConfig> {
+  overrides
+    .into_iter()
+    .map(|config| config.absolutize_paths(base_dir))
+    .collect()
+}
+
 fn merge_vecs<T: Clone>(base: &Option<Vec<T>>, overlay: &Option<Vec<T>>) -> Option<Vec<T>> {
   match (base, overlay) {
     (None, None) => None,
@@ -231,9 +624,38 @@ impl ConfigFile {
   pub fn from_file(path: &Path) -> Result<Self> {
     let content = std::fs::read_to_string(path)?;
     let config: ConfigFile = toml::from_str(&content)?;
+    config.validate()?;
     Ok(config.absolutize_paths(path.parent()))
   }
 
+  // Reject formatter settings which can't take effect, wherever formatters are defined.
+  fn validate(&self) -> Result<()> {
+    let profiles = self.profiles.iter().flat_map(HashMap::values);
+    let overrides = self
+      .overrides
+      .iter()
+      .chain(profiles.clone().flat_map(|profile| &profile.overrides))
+      .flatten();
+    let formatters = self
+      .formatters
+      .iter()
+      .chain(profiles.flat_map(|profile| &profile.formatters))
+      .chain(overrides.flat_map(|config| &config.formatters))
+      .flatten();
+
+    for (name, formatter) in formatters {
+      // The stderr of a long-lived process can't be told apart between requests
+      if formatter.protocol == Some(FormatterProtocol::Jsonrpc)
+        && formatter.fail_on_stderr == Some(true)
+      {
+        anyhow::bail!(
+          "Formatter {name} sets fail_on_stderr, which isn't supported with protocol = \"jsonrpc\""
+        );
+      }
+    }
+    Ok(())
+  }
+
   pub fn merge(base: &ConfigFile, overlay: &ConfigFile) -> ConfigFile {
     ConfigFile {
       query_paths: merge_vecs(&base.query_paths, &overlay.query_paths),
@@ -248,8 +670,15 @@ impl ConfigFile {
         .or_else(|| base.grammar_build_dir.clone()),
       grammars: merge_maps(&base.grammars, &overlay.grammars),
       languages: merge_maps(&base.languages, &overlay.languages),
+      aliases: merge_maps(&base.aliases, &overlay.aliases),
       formatters: merge_maps(&base.formatters, &overlay.formatters),
       plugins: merge_maps(&base.plugins, &overlay.plugins),
+      overrides: merge_vecs(&base.overrides, &overlay.overrides),
+      on_error: overlay.on_error.or(base.on_error),
+      safety_check: overlay.safety_check.or(base.safety_check),
+      max_injection_depth: overlay.max_injection_depth.or(base.max_injection_depth),
+      max_passes: overlay.max_passes.or(base.max_passes),
+      format_order: overlay.format_order.or(base.format_order),
       profiles: merge_maps(&base.profiles, &overlay.profiles),
     }
   }
@@ -265,8 +694,15 @@ impl ConfigFile {
       grammar_build_dir: profile.grammar_build_dir.clone().or(self.grammar_build_dir),
       grammars: merge_maps(&self.grammars, &profile.grammars),
       languages: merge_maps(&self.languages, &profile.languages),
+      aliases: merge_maps(&self.aliases, &profile.aliases),
       formatters: merge_maps(&self.formatters, &profile.formatters),
       plugins: merge_maps(&self.plugins, &profile.plugins),
+      overrides: merge_vecs(&self.overrides, &profile.overrides),
+      on_error: profile.on_error.or(self.on_error),
+      safety_check: profile.safety_check.or(self.safety_check),
+      max_injection_depth: profile.max_injection_depth.or(self.max_injection_depth),
+      max_passes: profile.max_passes.or(self.max_passes),
+      format_order: profile.format_order.or(self.format_order),
       profiles: self.profiles,
     }
   }
@@ -288,6 +724,12 @@ impl ConfigFile {
     self.grammar_build_dir = self
       .grammar_build_dir
       .map(|path| absolutize_path(path, base_dir));
+    self.formatters = self
+      .formatters
+      .map(|formatters| absolutize_formatters(formatters, base_dir));
+    self.overrides = self
+      .overrides
+      .map(|overrides| absolutize_overrides(overrides, base_dir));
     self.profiles = self.profiles.map(|profiles| {
       profiles
         .into_iter()
@@ -365,7 +807,16 @@ pub fn load(opts: LoadOpts) -> Result<Config> {
     cache_dir: xdg_dirs.place_data_file("cache")?,
     grammars: config_file.grammars.unwrap_or_default(),
     languages: config_file.languages.unwrap_or_default(),
+    aliases: config_file.aliases.unwrap_or_default(),
     formatters: config_file.formatters.unwrap_or_default(),
     plugins: config_file.plugins.unwrap_or_default(),
+    overrides: config_file.overrides.unwrap_or_default(),
+    on_error: config_file.on_error.unwrap_or_default(),
+    safety_check: config_file.safety_check.unwrap_or_default(),
+    max_injection_depth: config_file
+      .max_injection_depth
+      .unwrap_or(DEFAULT_MAX_INJECTION_DEPTH),
+    max_passes: config_file.max_passes.unwrap_or(1).max(1),
+    format_order: config_file.format_order.unwrap_or_default(),
   })
 }
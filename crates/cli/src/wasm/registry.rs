@@ -180,10 +180,11 @@ impl ComponentRegistry {
     let metadata_path = component_dir.join("metadata.toml");
     let download_path = component_dir.join("component.wasm");
 
-    if let Some(metadata) = read_metadata(&metadata_path)? {
-      if metadata.url == *url && download_path.is_file() {
-        return Ok((download_path, metadata.hash));
-      }
+    if let Some(metadata) = read_metadata(&metadata_path)?
+      && metadata.url == *url
+      && download_path.is_file()
+    {
+      return Ok((download_path, metadata.hash));
     }
 
     let hash = download_to_path(url, &download_path)?;
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use tree_sitter::Query;
+
+use pruner::{
+  api::{format::FormatContext, grammar::resolve_language, injections},
+  config::LanguageSpec,
+};
+
+mod common;
+
+#[test]
+fn resolves_names_through_grammars_and_aliases() -> Result<()> {
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([(
+    "sql".to_string(),
+    LanguageSpec::Formatters(vec!["pg_format".into()]),
+  )]);
+  let aliases = HashMap::from([
+    ("postgres".to_string(), "sql".to_string()),
+    ("markdown_inline".to_string(), "inline".to_string()),
+  ]);
+
+  let resolve = |name: &str| resolve_language(name, &grammars, &languages, &aliases);
+  assert_eq!(resolve("markdown"), "markdown");
+  assert_eq!(resolve("md"), "markdown");
+  assert_eq!(resolve("sql"), "sql");
+  assert_eq!(resolve("postgres"), "sql");
+  assert_eq!(resolve("md_inline"), "inline");
+  assert_eq!(resolve("c++"), "c++");
+
+  Ok(())
+}
+
+#[test]
+fn info_strings_and_filenames_name_the_language() -> Result<()> {
+  let mut grammars = common::grammars()?;
+  let markdown = grammars
+    .get_mut("markdown")
+    .ok_or_else(|| anyhow::anyhow!("Missing markdown grammar"))?;
+
+  let source = "```sql postgres\nselect 1\n```\n";
+  let mut parser = tree_sitter::Parser::new();
+  let regions =
+    injections::extract_language_injections(&mut parser, markdown, source.as_bytes(), None)?;
+  let langs = regions
+    .iter()
+    .map(|region| region.lang.as_str())
+    .collect::<Vec<_>>();
+  assert_eq!(langs, vec!["sql"]);
+
+  markdown.injections = Query::new(
+    &markdown.lang,
+    r#"(fenced_code_block
+  (info_string) @injection.filename
+  (code_fence_content) @injection.content)"#,
+  )?;
+  let source = "```queries/report.sql\nselect 1\n```\n\n```Makefile\nall:\n```\n";
+  let regions =
+    injections::extract_language_injections(&mut parser, markdown, source.as_bytes(), None)?;
+  let langs = regions
+    .iter()
+    .map(|region| region.lang.as_str())
+    .collect::<Vec<_>>();
+  assert_eq!(langs, vec!["sql", "Makefile"]);
+
+  Ok(())
+}
+
+#[test]
+fn aliased_regions_are_formatted() -> Result<()> {
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([(
+    "json".to_string(),
+    LanguageSpec::Formatters(vec!["jq".into()]),
+  )]);
+  let formatters = HashMap::from([("jq".to_string(), common::jq())]);
+  let aliases = HashMap::from([("geojson".to_string(), "json".to_string())]);
+
+  common::assert_fixture(
+    "aliases",
+    &FormatContext {
+      aliases: &aliases,
+      ..common::format_context(&grammars, &languages, &formatters)
+    },
+  )
+}
@@ -0,0 +1,89 @@
+use anyhow::Result;
+use std::{
+  collections::HashMap,
+  fs,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use pruner::{
+  api::format::FormatWarning,
+  config::{LanguageFormatters, LanguageSpec},
+};
+
+mod common;
+
+// Every paragraph of the document is part of one combined text region, as set by the query of the
+// fixture
+fn format_paragraphs(source: &str, script: &str) -> Result<(String, Vec<FormatWarning>)> {
+  let grammars = common::fixture_grammars("combined_injections")?;
+  let languages = text_formatter();
+  let formatters = HashMap::from([("script".to_string(), common::shell_formatter(script))]);
+
+  let context = common::format_context(&grammars, &languages, &formatters);
+  let result = common::format_source(source, "markdown", false, &context)?;
+  Ok((result, context.warnings.take()))
+}
+
+fn text_formatter() -> LanguageFormatters {
+  HashMap::from([(
+    "text".to_string(),
+    LanguageSpec::Formatters(vec!["script".into()]),
+  )])
+}
+
+#[test]
+fn combined_regions_are_formatted_as_one_document() -> Result<()> {
+  let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+  let input_path = std::env::temp_dir().join(format!("pruner-combined-{nanos}.txt"));
+
+  let grammars = common::fixture_grammars("combined_injections")?;
+  let languages = text_formatter();
+  let formatters = HashMap::from([(
+    "script".to_string(),
+    common::shell_formatter(&format!("tee {} | tr -s ' '", input_path.display())),
+  )]);
+  let context = common::format_context(&grammars, &languages, &formatters);
+  common::assert_fixture("combined_injections", &context)?;
+
+  assert_eq!(
+    fs::read_to_string(&input_path)?,
+    "Title\nhello   world\nfoo    bar"
+  );
+  assert!(context.warnings.take().is_empty());
+
+  fs::remove_file(input_path)?;
+  Ok(())
+}
+
+#[test]
+fn parts_keep_their_edges_when_lines_are_joined() -> Result<()> {
+  let (result, warnings) = format_paragraphs("one  two\n\nthree\n", "tr '\\n' ' ' | tr -s ' '")?;
+
+  assert_eq!(result, "one two\n\nthree\n");
+  assert!(warnings.is_empty());
+
+  Ok(())
+}
+
+#[test]
+fn regions_which_cant_be_split_are_left_alone() -> Result<()> {
+  let source = "one two\n\nthree\n";
+
+  let (result, warnings) = format_paragraphs(source, "sed 's/$/;/'")?;
+  assert_eq!(result, source);
+  assert_eq!(warnings.len(), 1);
+  assert_eq!(warnings[0].line, 1);
+  assert_eq!(warnings[0].language, "text");
+  assert!(
+    warnings[0]
+      .message
+      .contains("can't be split back into its 2 parts")
+  );
+
+  // A line break can't be moved into a paragraph which is a single line
+  let (result, warnings) = format_paragraphs(source, "tr ' ' '\\n'")?;
+  assert_eq!(result, source);
+  assert_eq!(warnings.len(), 1);
+
+  Ok(())
+}
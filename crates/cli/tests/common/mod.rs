@@ -1,12 +1,26 @@
 use anyhow::Result;
 use fslock::LockFile;
-use std::{collections::HashMap, fs::File, io::Read, path::PathBuf};
+use std::{
+  collections::HashMap,
+  fs::{self, File},
+  io::Read,
+  path::PathBuf,
+  sync::LazyLock,
+};
+use tree_sitter::Query;
 
 use pruner::{
-  api::grammar::{self, Grammars},
-  config::{FormatterSpecs, LanguageFormatters},
+  api::{
+    format::{self, FormatContext, FormatOpts},
+    grammar::{self, Grammars},
+  },
+  config::{FormatterSpec, FormatterSpecs, LanguageFormatters, LanguageSpec},
+  wasm::formatter::WasmFormatter,
 };
 
+static WASM_FORMATTER: LazyLock<WasmFormatter> =
+  LazyLock::new(|| WasmFormatter::new("cache".into()).expect("Wasm formatter should load"));
+
 #[allow(dead_code)]
 pub fn formatters() -> FormatterSpecs {
   HashMap::from([
@@ -21,6 +35,8 @@ pub fn formatters() -> FormatterSpecs {
         ]),
         stdin: None,
         fail_on_stderr: None,
+        temp_file: None,
+        protocol: None,
       },
     ),
     (
@@ -34,11 +50,66 @@ pub fn formatters() -> FormatterSpecs {
         ]),
         stdin: Some(true),
         fail_on_stderr: None,
+        temp_file: None,
+        protocol: None,
       },
     ),
   ])
 }
 
+// Formatters which are expected to be installed wherever the tests run, unlike those above.
+#[allow(dead_code)]
+pub fn jq() -> FormatterSpec {
+  FormatterSpec {
+    cmd: "jq".into(),
+    args: vec![".".into()],
+    stdin: None,
+    fail_on_stderr: None,
+    temp_file: None,
+    protocol: None,
+  }
+}
+
+#[allow(dead_code)]
+pub fn rustfmt() -> FormatterSpec {
+  FormatterSpec {
+    cmd: "rustfmt".into(),
+    // The settings of the repository itself must not apply
+    args: vec![
+      "--edition=2021".into(),
+      "--config-path=/dev/null".into(),
+      "--config".into(),
+      "max_width=$textwidth".into(),
+    ],
+    stdin: None,
+    fail_on_stderr: None,
+    temp_file: None,
+    protocol: None,
+  }
+}
+
+// A formatter running the given shell script, with the source on stdin.
+#[allow(dead_code)]
+pub fn shell_formatter(script: &str) -> FormatterSpec {
+  FormatterSpec {
+    cmd: "sh".into(),
+    args: vec!["-c".into(), script.into()],
+    stdin: None,
+    fail_on_stderr: None,
+    temp_file: None,
+    protocol: None,
+  }
+}
+
+#[allow(dead_code)]
+pub fn format_context<'a>(
+  grammars: &'a Grammars,
+  languages: &'a LanguageFormatters,
+  formatters: &'a FormatterSpecs,
+) -> FormatContext<'a> {
+  FormatContext::new(grammars, languages, formatters, &WASM_FORMATTER)
+}
+
 #[allow(dead_code)]
 pub fn grammars() -> Result<Grammars> {
   let mut file = LockFile::open("tests/fixtures/.build.lock")?;
@@ -51,11 +122,42 @@ pub fn grammars() -> Result<Grammars> {
   )
 }
 
+// The test grammars, with the injections query of markdown replaced by `query`.
+#[allow(dead_code)]
+pub fn grammars_with_markdown_query(query: &str) -> Result<Grammars> {
+  let mut grammars = grammars()?;
+  let markdown = grammars
+    .get_mut("markdown")
+    .ok_or_else(|| anyhow::anyhow!("Missing markdown grammar"))?;
+  markdown.injections = Query::new(&markdown.lang, query)?;
+  Ok(grammars)
+}
+
+// The grammars for the fixture directory `name`, using its `injections.scm` query for markdown if it
+// has one.
+#[allow(dead_code)]
+pub fn fixture_grammars(name: &str) -> Result<Grammars> {
+  let query = PathBuf::from("tests/fixtures/tests/")
+    .join(name)
+    .join("injections.scm");
+  if query.exists() {
+    grammars_with_markdown_query(&fs::read_to_string(query)?)
+  } else {
+    grammars()
+  }
+}
+
 #[allow(dead_code)]
 pub fn languages() -> LanguageFormatters {
   HashMap::from([
-    ("markdown".to_string(), vec!["prettier".into()]),
-    ("clojure".to_string(), vec!["cljfmt".into()]),
+    (
+      "markdown".to_string(),
+      LanguageSpec::Formatters(vec!["prettier".into()]),
+    ),
+    (
+      "clojure".to_string(),
+      LanguageSpec::Formatters(vec!["cljfmt".into()]),
+    ),
   ])
 }
 
@@ -69,3 +171,60 @@ pub fn load_file(path: &str) -> String {
     .expect("Should be able to read source file");
   contents
 }
+
+// Format `source` as a document of `language` with a print width of 80.
+#[allow(dead_code)]
+pub fn format_source(
+  source: &str,
+  language: &str,
+  format_root: bool,
+  context: &FormatContext,
+) -> Result<String> {
+  let result = format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language,
+      path: None,
+      indent_width: None,
+      variables: &[],
+    },
+    format_root,
+    true,
+    context,
+  )?;
+  Ok(String::from_utf8(result)?)
+}
+
+// Format the `input` file of the fixture directory `name` and compare the result with its `output`
+// file. The language is the grammar registering the extension of the files, or else the extension
+// itself.
+#[allow(dead_code)]
+pub fn assert_fixture(name: &str, context: &FormatContext) -> Result<()> {
+  let dir = PathBuf::from("tests/fixtures/tests/").join(name);
+  let input = fs::read_dir(&dir)?
+    .map(|entry| entry.map(|entry| entry.path()))
+    .collect::<Result<Vec<_>, _>>()?
+    .into_iter()
+    .find(|path| path.file_stem().is_some_and(|stem| stem == "input"))
+    .ok_or_else(|| anyhow::anyhow!("Missing input file in {}", dir.display()))?;
+  let extension = input
+    .extension()
+    .and_then(|extension| extension.to_str())
+    .unwrap_or_default();
+  let language = context
+    .grammars
+    .values()
+    .find(|grammar| {
+      grammar
+        .file_types
+        .iter()
+        .any(|file_type| file_type == extension)
+    })
+    .map_or(extension, |grammar| grammar.name.as_str());
+
+  let result = format_source(&fs::read_to_string(&input)?, language, true, context)?;
+  let expected = fs::read_to_string(dir.join(format!("output.{extension}")))?;
+  assert_eq!(result, expected, "fixture {name}");
+  Ok(())
+}
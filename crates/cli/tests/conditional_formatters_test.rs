@@ -1,11 +1,16 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path, sync::Arc};
 
 use anyhow::Result;
 
 use pruner::{
-  api::format::{self, FormatContext, FormatOpts},
-  config::LanguageFormatSpec,
-  wasm::formatter::WasmFormatter,
+  api::{
+    format::{self, FormatContext, FormatOpts},
+    overrides::Overrides,
+  },
+  config::{
+    DepthCondition, FormatCondition, FormatterSpec, LanguageFormatSpec, LanguageFormatters,
+    LanguageSpec, PathGlob,
+  },
 };
 
 mod common;
@@ -14,15 +19,16 @@ mod common;
 fn injections_only_pipeline_condition_test() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
-  let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let languages = HashMap::from([(
     "clojure".to_string(),
-    vec![LanguageFormatSpec::Table {
+    LanguageSpec::Formatters(vec![LanguageFormatSpec::Table {
       formatter: "cljfmt".into(),
       run_in_root: false,
       run_in_injections: true,
-    }],
+      optional: false,
+      when: None,
+    }]),
   )]);
 
   let source = r"(println 1  )";
@@ -32,15 +38,13 @@ fn injections_only_pipeline_condition_test() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )
   .unwrap();
 
@@ -56,15 +60,13 @@ fn injections_only_pipeline_condition_test() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "markdown",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )
   .unwrap();
 
@@ -83,15 +85,16 @@ fn injections_only_pipeline_condition_test() -> Result<()> {
 fn root_only_pipeline_condition_test() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
-  let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let languages = HashMap::from([(
     "clojure".to_string(),
-    vec![LanguageFormatSpec::Table {
+    LanguageSpec::Formatters(vec![LanguageFormatSpec::Table {
       formatter: "cljfmt".into(),
       run_in_root: true,
       run_in_injections: false,
-    }],
+      optional: false,
+      when: None,
+    }]),
   )]);
 
   let source = r"(println 1  )";
@@ -101,15 +104,13 @@ fn root_only_pipeline_condition_test() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )
   .unwrap();
 
@@ -125,15 +126,13 @@ fn root_only_pipeline_condition_test() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "markdown",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )
   .unwrap();
 
@@ -141,3 +140,216 @@ fn root_only_pipeline_condition_test() -> Result<()> {
 
   Ok(())
 }
+
+fn format_text(languages: &LanguageFormatters) -> Result<String> {
+  let grammars = HashMap::new();
+  let formatters = HashMap::from([
+    ("upper".to_string(), common::shell_formatter("tr a-z A-Z")),
+    (
+      "missing".to_string(),
+      FormatterSpec {
+        cmd: "pruner-missing-formatter".into(),
+        args: Vec::new(),
+        stdin: None,
+        fail_on_stderr: None,
+        temp_file: None,
+        protocol: None,
+      },
+    ),
+  ]);
+
+  let result = format::format(
+    b"hello",
+    &FormatOpts {
+      printwidth: 80,
+      language: "text",
+      path: None,
+      indent_width: None,
+      variables: &[],
+    },
+    true,
+    true,
+    &common::format_context(&grammars, languages, &formatters),
+  )?;
+
+  Ok(String::from_utf8(result)?)
+}
+
+#[test]
+fn first_available_formatter_is_selected() -> Result<()> {
+  let languages = HashMap::from([(
+    "text".to_string(),
+    LanguageSpec::Formatters(vec![LanguageFormatSpec::Alternatives(vec![
+      "missing".into(),
+      "upper".into(),
+    ])]),
+  )]);
+
+  assert_eq!(format_text(&languages)?, "HELLO");
+
+  Ok(())
+}
+
+#[test]
+fn missing_formatter_fails_unless_optional() -> Result<()> {
+  let languages = HashMap::from([(
+    "text".to_string(),
+    LanguageSpec::Formatters(vec![LanguageFormatSpec::Alternatives(vec![
+      "missing".into(),
+      "unknown".into(),
+    ])]),
+  )]);
+
+  let err = format_text(&languages).expect_err("the formatters should be missing");
+  assert_eq!(
+    "No available formatter among: missing, unknown",
+    err.to_string()
+  );
+
+  // A lone formatter is run the same as before alternatives existed
+  let languages = HashMap::from([(
+    "text".to_string(),
+    LanguageSpec::Formatters(vec!["missing".into()]),
+  )]);
+
+  let err = format_text(&languages).expect_err("the formatter should be missing");
+  assert_eq!("Failed to run formatter: missing", err.to_string());
+
+  let languages = HashMap::from([(
+    "text".to_string(),
+    LanguageSpec::Formatters(vec!["unknown".into()]),
+  )]);
+
+  assert_eq!(format_text(&languages)?, "hello");
+
+  let languages = HashMap::from([(
+    "text".to_string(),
+    LanguageSpec::Formatters(vec![
+      LanguageFormatSpec::Table {
+        formatter: "missing".into(),
+        run_in_root: true,
+        run_in_injections: true,
+        optional: true,
+        when: None,
+      },
+      "upper".into(),
+    ]),
+  )]);
+
+  assert_eq!(format_text(&languages)?, "HELLO");
+
+  Ok(())
+}
+
+fn format_markdown(languages: &LanguageFormatters, path: &Path) -> Result<String> {
+  format_markdown_in(Path::new(""), languages, path)
+}
+
+// Format a markdown document while formatting the directory `root`
+fn format_markdown_in(root: &Path, languages: &LanguageFormatters, path: &Path) -> Result<String> {
+  let grammars = common::grammars()?;
+  let formatters = HashMap::from([("upper".to_string(), common::shell_formatter("tr a-z A-Z"))]);
+
+  let source = r"# Title
+
+```text
+hello
+```
+";
+
+  let result = format::format_document(
+    source.as_bytes(),
+    path,
+    &FormatOpts {
+      printwidth: 80,
+      language: "markdown",
+      path: None,
+      indent_width: None,
+      variables: &[],
+    },
+    true,
+    &FormatContext {
+      overrides: Arc::new(Overrides::new(root, &[])?),
+      ..common::format_context(&grammars, languages, &formatters)
+    },
+  )?;
+
+  Ok(String::from_utf8(result)?)
+}
+
+fn conditional_upper(condition: FormatCondition) -> LanguageFormatters {
+  HashMap::from([(
+    "text".to_string(),
+    LanguageSpec::Formatters(vec![LanguageFormatSpec::Table {
+      formatter: "upper".into(),
+      run_in_root: true,
+      run_in_injections: true,
+      optional: false,
+      when: Some(condition),
+    }]),
+  )])
+}
+
+#[test]
+fn injection_context_conditions() -> Result<()> {
+  let formatted = "# Title\n\n```text\nHELLO\n```\n";
+  let unformatted = "# Title\n\n```text\nhello\n```\n";
+  let path = Path::new("docs/readme.md");
+
+  let languages = conditional_upper(FormatCondition {
+    parent_language: Some("markdown".into()),
+    ..Default::default()
+  });
+  assert_eq!(format_markdown(&languages, path)?, formatted);
+
+  let languages = conditional_upper(FormatCondition {
+    parent_language: Some("clojure".into()),
+    ..Default::default()
+  });
+  assert_eq!(format_markdown(&languages, path)?, unformatted);
+
+  let languages = conditional_upper(FormatCondition {
+    depth: Some(DepthCondition::Range {
+      min: None,
+      max: Some(1),
+    }),
+    ..Default::default()
+  });
+  assert_eq!(format_markdown(&languages, path)?, formatted);
+
+  let languages = conditional_upper(FormatCondition {
+    depth: Some(DepthCondition::Exact(2)),
+    ..Default::default()
+  });
+  assert_eq!(format_markdown(&languages, path)?, unformatted);
+
+  let languages = conditional_upper(FormatCondition {
+    path: Some(PathGlob::new("docs/**")?),
+    ..Default::default()
+  });
+  assert_eq!(format_markdown(&languages, path)?, formatted);
+  assert_eq!(
+    format_markdown(&languages, Path::new("src/readme.md"))?,
+    unformatted
+  );
+
+  // Paths are relative to the directory being formatted, not the working directory
+  assert_eq!(
+    format_markdown_in(
+      Path::new("/repo"),
+      &languages,
+      Path::new("/repo/docs/readme.md")
+    )?,
+    formatted
+  );
+  assert_eq!(
+    format_markdown_in(
+      Path::new("/repo/docs"),
+      &languages,
+      Path::new("/repo/docs/readme.md")
+    )?,
+    unformatted
+  );
+
+  Ok(())
+}
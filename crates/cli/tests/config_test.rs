@@ -1,4 +1,7 @@
-use pruner::config::{ConfigFile, ProfileConfig};
+use pruner::config::{
+  ConfigFile, LanguageFormatSpec, LanguageSettings, LanguageSpec, ProfileConfig, TempFileLocation,
+  TempFileSpec,
+};
 use std::{
   collections::HashMap,
   fs::{self, File},
@@ -66,8 +69,14 @@ fn merges_configs_with_overlay_priority() {
     grammar_download_dir: Some(PathBuf::from("base_downloads")),
     grammar_build_dir: Some(PathBuf::from("base_build")),
     languages: Some(HashMap::from([
-      ("markdown".to_string(), vec!["base_fmt".into()]),
-      ("clojure".to_string(), vec!["base_clj".into()]),
+      (
+        "markdown".to_string(),
+        LanguageSpec::Formatters(vec!["base_fmt".into()]),
+      ),
+      (
+        "clojure".to_string(),
+        LanguageSpec::Formatters(vec!["base_clj".into()]),
+      ),
     ])),
     formatters: Some(HashMap::from([
       (
@@ -77,6 +86,8 @@ fn merges_configs_with_overlay_priority() {
           args: Vec::new(),
           stdin: None,
           fail_on_stderr: None,
+          temp_file: None,
+          protocol: None,
         },
       ),
       (
@@ -86,6 +97,8 @@ fn merges_configs_with_overlay_priority() {
           args: Vec::new(),
           stdin: None,
           fail_on_stderr: None,
+          temp_file: None,
+          protocol: None,
         },
       ),
     ])),
@@ -97,8 +110,14 @@ fn merges_configs_with_overlay_priority() {
     grammar_paths: Some(vec![PathBuf::from("overlay_grammar")]),
     grammar_download_dir: Some(PathBuf::from("overlay_downloads")),
     languages: Some(HashMap::from([
-      ("markdown".to_string(), vec!["overlay_fmt".into()]),
-      ("rust".to_string(), vec!["rust_fmt".into()]),
+      (
+        "markdown".to_string(),
+        LanguageSpec::Formatters(vec!["overlay_fmt".into()]),
+      ),
+      (
+        "rust".to_string(),
+        LanguageSpec::Formatters(vec!["rust_fmt".into()]),
+      ),
     ])),
     formatters: Some(HashMap::from([
       (
@@ -108,6 +127,8 @@ fn merges_configs_with_overlay_priority() {
           args: Vec::new(),
           stdin: None,
           fail_on_stderr: None,
+          temp_file: None,
+          protocol: None,
         },
       ),
       (
@@ -117,6 +138,8 @@ fn merges_configs_with_overlay_priority() {
           args: Vec::new(),
           stdin: None,
           fail_on_stderr: None,
+          temp_file: None,
+          protocol: None,
         },
       ),
     ])),
@@ -155,6 +178,8 @@ fn merges_configs_with_overlay_priority() {
           args: Vec::new(),
           stdin: None,
           fail_on_stderr: None,
+          temp_file: None,
+          protocol: None,
         },
       ),
       (
@@ -164,6 +189,8 @@ fn merges_configs_with_overlay_priority() {
           args: Vec::new(),
           stdin: None,
           fail_on_stderr: None,
+          temp_file: None,
+          protocol: None,
         },
       ),
       (
@@ -173,6 +200,8 @@ fn merges_configs_with_overlay_priority() {
           args: Vec::new(),
           stdin: None,
           fail_on_stderr: None,
+          temp_file: None,
+          protocol: None,
         },
       ),
     ]),
@@ -182,9 +211,18 @@ fn merges_configs_with_overlay_priority() {
   let languages = merged.languages.unwrap();
   assert_eq!(
     HashMap::from([
-      ("clojure".to_string(), vec!["base_clj".into()]),
-      ("markdown".to_string(), vec!["overlay_fmt".into()]),
-      ("rust".to_string(), vec!["rust_fmt".into()]),
+      (
+        "clojure".to_string(),
+        LanguageSpec::Formatters(vec!["base_clj".into()])
+      ),
+      (
+        "markdown".to_string(),
+        LanguageSpec::Formatters(vec!["overlay_fmt".into()])
+      ),
+      (
+        "rust".to_string(),
+        LanguageSpec::Formatters(vec!["rust_fmt".into()])
+      ),
     ]),
     languages
   );
@@ -199,7 +237,7 @@ fn applies_profile_overrides() {
     grammar_build_dir: Some(PathBuf::from("base_build")),
     languages: Some(HashMap::from([(
       "markdown".to_string(),
-      vec!["base_fmt".into()],
+      LanguageSpec::Formatters(vec!["base_fmt".into()]),
     )])),
     formatters: Some(HashMap::from([(
       "fmt".to_string(),
@@ -208,6 +246,8 @@ fn applies_profile_overrides() {
         args: Vec::new(),
         stdin: None,
         fail_on_stderr: None,
+        temp_file: None,
+        protocol: None,
       },
     )])),
     ..Default::default()
@@ -217,8 +257,14 @@ fn applies_profile_overrides() {
     query_paths: Some(vec![PathBuf::from("profile_query")]),
     grammar_download_dir: Some(PathBuf::from("profile_downloads")),
     languages: Some(HashMap::from([
-      ("markdown".to_string(), vec!["profile_fmt".into()]),
-      ("rust".to_string(), vec!["rust_fmt".into()]),
+      (
+        "markdown".to_string(),
+        LanguageSpec::Formatters(vec!["profile_fmt".into()]),
+      ),
+      (
+        "rust".to_string(),
+        LanguageSpec::Formatters(vec!["rust_fmt".into()]),
+      ),
     ])),
     ..Default::default()
   };
@@ -245,8 +291,14 @@ fn applies_profile_overrides() {
   let languages = result.languages.unwrap();
   assert_eq!(
     HashMap::from([
-      ("markdown".to_string(), vec!["profile_fmt".into()]),
-      ("rust".to_string(), vec!["rust_fmt".into()]),
+      (
+        "markdown".to_string(),
+        LanguageSpec::Formatters(vec!["profile_fmt".into()])
+      ),
+      (
+        "rust".to_string(),
+        LanguageSpec::Formatters(vec!["rust_fmt".into()])
+      ),
     ]),
     languages
   );
@@ -260,6 +312,8 @@ fn applies_profile_overrides() {
         args: Vec::new(),
         stdin: None,
         fail_on_stderr: None,
+        temp_file: None,
+        protocol: None,
       },
     )]),
     formatters
@@ -305,8 +359,227 @@ rust = ["rustfmt"]
   assert_eq!(
     ci_profile.languages,
     Some(HashMap::from([
-      ("markdown".to_string(), vec!["ci_prettier".into()]),
-      ("rust".to_string(), vec!["rustfmt".into()]),
+      (
+        "markdown".to_string(),
+        LanguageSpec::Formatters(vec!["ci_prettier".into()])
+      ),
+      (
+        "rust".to_string(),
+        LanguageSpec::Formatters(vec!["rustfmt".into()])
+      ),
+    ]))
+  );
+}
+
+#[test]
+fn loads_formatter_temp_file_settings() {
+  let temp_dir = unique_temp_dir();
+  let config_path = temp_dir.join("config.toml");
+
+  let mut file = File::create(&config_path).expect("should create config file");
+  writeln!(
+    file,
+    r#"
+[formatters]
+prettier = {{ cmd = "prettier", args = [], stdin = false, temp_file = "adjacent" }}
+sqlfluff = {{ cmd = "sqlfluff", args = [], stdin = false, temp_file = {{ dir = "sandbox" }} }}
+"#
+  )
+  .expect("should write config file");
+
+  let config = ConfigFile::from_file(&config_path).expect("should load config");
+  let formatters = config.formatters.expect("formatters should be set");
+
+  assert_eq!(
+    formatters.get("prettier").unwrap().temp_file,
+    Some(TempFileSpec::Location(TempFileLocation::Adjacent))
+  );
+  assert_eq!(
+    formatters.get("sqlfluff").unwrap().temp_file,
+    Some(TempFileSpec::Sandbox {
+      dir: temp_dir.join("sandbox")
+    })
+  );
+}
+
+#[test]
+fn loads_language_formatter_alternatives() {
+  let temp_dir = unique_temp_dir();
+  let config_path = temp_dir.join("config.toml");
+
+  let mut file = File::create(&config_path).expect("should create config file");
+  writeln!(
+    file,
+    r#"
+[languages]
+python = [["ruff_format", {{ formatter = "black", optional = true }}], "isort"]
+"#
+  )
+  .expect("should write config file");
+
+  let config = ConfigFile::from_file(&config_path).expect("should load config");
+  let languages = config.languages.expect("languages should be set");
+
+  assert_eq!(
+    languages.get("python").unwrap().formatters(),
+    &vec![
+      LanguageFormatSpec::Alternatives(vec![
+        "ruff_format".into(),
+        LanguageFormatSpec::Table {
+          formatter: "black".into(),
+          run_in_root: true,
+          run_in_injections: true,
+          optional: true,
+          when: None,
+        },
+      ]),
+      "isort".into(),
+    ]
+  );
+}
+
+#[test]
+fn invalid_path_conditions_fail_to_load() {
+  let temp_dir = unique_temp_dir();
+  let config_path = temp_dir.join("config.toml");
+
+  let mut file = File::create(&config_path).expect("should create config file");
+  writeln!(
+    file,
+    r#"
+[languages]
+sql = [{{ formatter = "sqlfluff", when = {{ path = "migrations/[**" }} }}]
+"#
+  )
+  .expect("should write config file");
+
+  ConfigFile::from_file(&config_path).expect_err("the glob should be rejected");
+}
+
+#[test]
+fn fail_on_stderr_is_rejected_for_jsonrpc_formatters() {
+  let temp_dir = unique_temp_dir();
+  let config_path = temp_dir.join("config.toml");
+
+  let mut file = File::create(&config_path).expect("should create config file");
+  writeln!(
+    file,
+    r#"
+[[overrides]]
+files = ["*.sql"]
+
+[overrides.formatters.sqlfmt]
+cmd = "sqlfmt"
+args = ["--daemon"]
+protocol = "jsonrpc"
+fail_on_stderr = true
+"#
+  )
+  .expect("should write config file");
+
+  let err = ConfigFile::from_file(&config_path).expect_err("fail_on_stderr should be rejected");
+  assert_eq!(
+    err.to_string(),
+    "Formatter sqlfmt sets fail_on_stderr, which isn't supported with protocol = \"jsonrpc\""
+  );
+}
+
+#[test]
+fn loads_language_settings_tables() {
+  let temp_dir = unique_temp_dir();
+  let config_path = temp_dir.join("config.toml");
+
+  let mut file = File::create(&config_path).expect("should create config file");
+  writeln!(
+    file,
+    r#"
+[languages]
+markdown = ["prettier"]
+
+[languages.sql]
+formatters = ["pg_format"]
+print_width = 100
+min_print_width = 40
+indent_width = 4
+tab_width = 8
+"#
+  )
+  .expect("should write config file");
+
+  let config = ConfigFile::from_file(&config_path).expect("should load config");
+  let languages = config.languages.expect("languages should be set");
+
+  assert_eq!(
+    languages.get("markdown").unwrap(),
+    &LanguageSpec::Formatters(vec!["prettier".into()])
+  );
+  assert_eq!(
+    languages.get("sql").unwrap(),
+    &LanguageSpec::Table {
+      formatters: vec!["pg_format".into()],
+      settings: LanguageSettings {
+        print_width: Some(100),
+        min_print_width: Some(40),
+        max_print_width: None,
+        indent_width: Some(4),
+        tab_width: Some(8),
+        placeholders: vec![],
+      },
+    }
+  );
+}
+
+#[test]
+fn unknown_language_settings_fail_to_load() {
+  let temp_dir = unique_temp_dir();
+  let config_path = temp_dir.join("config.toml");
+
+  let mut file = File::create(&config_path).expect("should create config file");
+  writeln!(
+    file,
+    r#"
+[languages.sql]
+formatters = ["pg_format"]
+print_widht = 100
+"#
+  )
+  .expect("should write config file");
+
+  let err = ConfigFile::from_file(&config_path).expect_err("the typo should be rejected");
+  assert!(
+    err.to_string().contains("unknown field `print_widht`"),
+    "{err}"
+  );
+}
+
+#[test]
+fn loads_and_merges_language_aliases() {
+  let temp_dir = unique_temp_dir();
+  let config_path = temp_dir.join("config.toml");
+
+  let mut file = File::create(&config_path).expect("should create config file");
+  writeln!(
+    file,
+    r#"
+[aliases]
+postgres = "sql"
+py = "python"
+"#
+  )
+  .expect("should write config file");
+
+  let base = ConfigFile::from_file(&config_path).expect("should load config");
+  let overlay = ConfigFile {
+    aliases: Some(HashMap::from([("py".into(), "python3".into())])),
+    ..Default::default()
+  };
+
+  let merged = ConfigFile::merge(&base, &overlay);
+  assert_eq!(
+    merged.aliases,
+    Some(HashMap::from([
+      ("postgres".into(), "sql".into()),
+      ("py".into(), "python3".into()),
     ]))
   );
 }
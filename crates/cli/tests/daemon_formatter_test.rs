@@ -0,0 +1,175 @@
+use anyhow::Result;
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+
+use pruner::{
+  api::format::{self, Daemons, FormatContext, FormatOpts},
+  config::{FormatterProtocol, FormatterSpec, FormatterSpecs, LanguageFormatters, LanguageSpec},
+};
+
+mod common;
+
+/// A minimal formatter daemon which upper-cases the source, and prefixes it with its own pid so that
+/// tests can observe whether the process was reused.
+const DAEMON: &str = r#"
+import json, os, sys
+
+def read_message():
+    length = None
+    while True:
+        line = sys.stdin.buffer.readline()
+        if not line:
+            return None
+        line = line.strip()
+        if not line:
+            break
+        name, value = line.split(b":", 1)
+        if name.lower() == b"content-length":
+            length = int(value)
+    return json.loads(sys.stdin.buffer.read(length))
+
+while True:
+    request = read_message()
+    if request is None:
+        break
+    params = request["params"]
+    if params["source"] == "fail":
+        body = {"jsonrpc": "2.0", "id": request["id"], "error": {"code": 1, "message": "bad input"}}
+    else:
+        source = "%d:%d:%s" % (os.getpid(), params["print_width"], params["source"].upper())
+        body = {"jsonrpc": "2.0", "id": request["id"], "result": {"source": source}}
+    data = json.dumps(body).encode()
+    sys.stdout.buffer.write(b"Content-Length: %d\r\n\r\n" % len(data) + data)
+    sys.stdout.buffer.flush()
+"#;
+
+fn formatters(script: &str) -> FormatterSpecs {
+  HashMap::from([(
+    "upper".to_string(),
+    FormatterSpec {
+      cmd: "python3".into(),
+      args: vec!["-c".into(), script.into()],
+      stdin: None,
+      fail_on_stderr: None,
+      temp_file: None,
+      protocol: Some(FormatterProtocol::Jsonrpc),
+    },
+  )])
+}
+
+fn languages() -> LanguageFormatters {
+  HashMap::from([(
+    "text".to_string(),
+    LanguageSpec::Formatters(vec!["upper".into()]),
+  )])
+}
+
+fn format_text(source: &str, context: &FormatContext) -> Result<String> {
+  let result = format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 60,
+      language: "text",
+      path: None,
+      indent_width: None,
+      variables: &[],
+    },
+    true,
+    true,
+    context,
+  )?;
+
+  Ok(String::from_utf8(result)?)
+}
+
+#[test]
+fn daemon_is_reused_across_requests() -> Result<()> {
+  let (grammars, languages, formatters) = (HashMap::new(), languages(), formatters(DAEMON));
+  let context = common::format_context(&grammars, &languages, &formatters);
+
+  let first = format_text("hello", &context)?;
+  let second = format_text("world", &context)?;
+
+  let (first_pid, first_rest) = first.split_once(':').unwrap();
+  let (second_pid, second_rest) = second.split_once(':').unwrap();
+
+  assert_eq!(first_rest, "60:HELLO");
+  assert_eq!(second_rest, "60:WORLD");
+  assert_eq!(
+    first_pid, second_pid,
+    "the formatter process should be reused"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn daemon_error_response() -> Result<()> {
+  let (grammars, languages, formatters) = (HashMap::new(), languages(), formatters(DAEMON));
+  let context = common::format_context(&grammars, &languages, &formatters);
+
+  let err = format_text("fail", &context).expect_err("the formatter should fail");
+  assert_eq!(
+    "Failed to run formatter python3 (1): bad input",
+    err.root_cause().to_string()
+  );
+
+  let result = format_text("ok", &context)?;
+  assert!(result.ends_with(":60:OK"), "{result}");
+
+  Ok(())
+}
+
+#[test]
+fn daemons_are_stopped_on_shutdown() -> Result<()> {
+  let (grammars, languages, formatters) = (HashMap::new(), languages(), formatters(DAEMON));
+  let context = common::format_context(&grammars, &languages, &formatters);
+
+  let result = format_text("hello", &context)?;
+  let (pid, _) = result.split_once(':').unwrap();
+  let process = PathBuf::from(format!("/proc/{pid}"));
+  assert!(process.exists());
+
+  // The process must be reaped too, or it would stay around as a zombie
+  context.daemons.shutdown();
+  assert!(!process.exists(), "the formatter process should be gone");
+
+  Ok(())
+}
+
+#[test]
+fn unresponsive_daemons_time_out() -> Result<()> {
+  let (grammars, languages) = (HashMap::new(), languages());
+  let formatters = formatters("import sys, time; sys.stdin.readline(); time.sleep(60)");
+  let context = FormatContext {
+    daemons: Arc::new(Daemons::with_timeout(Duration::from_millis(200))),
+    ..common::format_context(&grammars, &languages, &formatters)
+  };
+
+  let err = format_text("hello", &context).expect_err("the formatter should time out");
+  assert_eq!(
+    "Formatter process didn't respond within 0.2s",
+    err.root_cause().to_string()
+  );
+
+  Ok(())
+}
+
+#[test]
+fn stderr_of_failed_daemons_is_reported() -> Result<()> {
+  let (grammars, languages) = (HashMap::new(), languages());
+  let formatters = formatters("import sys; sys.stderr.write('bad config\\n'); sys.exit(1)");
+  let context = common::format_context(&grammars, &languages, &formatters);
+
+  let err = format_text("hello", &context).expect_err("the formatter should fail");
+  let message = format!("{err:#}");
+  assert!(
+    message.contains("Formatter process python3 wrote to stderr:\nbad config"),
+    "{message}"
+  );
+  assert_eq!(
+    "Formatter process closed stdout",
+    err.root_cause().to_string()
+  );
+
+  Ok(())
+}
@@ -0,0 +1,86 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::{
+    format::{self, FormatOpts},
+    text::Encoding,
+  },
+  config::LanguageSpec,
+};
+
+mod common;
+
+fn format_text(source: &[u8], script: &str) -> Result<Vec<u8>> {
+  let grammars = HashMap::new();
+  let languages = HashMap::from([(
+    "text".to_string(),
+    LanguageSpec::Formatters(vec!["script".into()]),
+  )]);
+  let formatters = HashMap::from([("script".to_string(), common::shell_formatter(script))]);
+
+  format::format(
+    source,
+    &FormatOpts {
+      printwidth: 80,
+      language: "text",
+      path: None,
+      indent_width: None,
+      variables: &[],
+    },
+    true,
+    true,
+    &common::format_context(&grammars, &languages, &formatters),
+  )
+}
+
+#[test]
+fn crlf_line_endings_are_restored() -> Result<()> {
+  let result = format_text(b"a\r\nb\r\n", "sed 's/$/!/'")?;
+  assert_eq!(result, b"a!\r\nb!\r\n");
+
+  let result = format_text(b"a\nb\n", "sed 's/$/!/'")?;
+  assert_eq!(result, b"a!\nb!\n");
+
+  Ok(())
+}
+
+#[test]
+fn bom_is_restored() -> Result<()> {
+  let result = format_text(b"\xEF\xBB\xBFa\r\n", "tr a-z A-Z")?;
+  assert_eq!(result, b"\xEF\xBB\xBFA\r\n");
+
+  Ok(())
+}
+
+#[test]
+fn invalid_utf8_is_passed_through() -> Result<()> {
+  let source = b"caf\xE9\n";
+  assert_eq!(format_text(source, "tr a-z A-Z")?, source);
+
+  let result = format_text(b"cafe\n", "cat > /dev/null; printf 'caf\\351\\n'")?;
+  assert_eq!(result, b"cafe\n");
+
+  Ok(())
+}
+
+#[test]
+fn encoding_round_trip() {
+  let source = b"\xEF\xBB\xBFa\r\nb\rc\r\n";
+  let encoding = Encoding::detect(source);
+  assert_eq!(
+    encoding,
+    Encoding {
+      bom: true,
+      crlf: true
+    }
+  );
+
+  let normalized = encoding.normalize(source);
+  assert_eq!(normalized, b"a\nb\rc\n");
+  assert_eq!(encoding.restore(&normalized), source);
+
+  let encoding = Encoding::detect(b"a\nb\r\n");
+  assert!(!encoding.bom);
+  assert!(!encoding.crlf);
+}
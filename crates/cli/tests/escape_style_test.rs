@@ -0,0 +1,182 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::text::{EscapeStyle, Escaping},
+  config::LanguageSpec,
+};
+
+mod common;
+
+const SQUEEZE: &str = "tr -s ' '";
+
+fn format_escaped(style: &str, source: &str) -> Result<String> {
+  let grammars = common::grammars_with_markdown_query(&format!(
+    r#"((code_fence_content) @injection.content
+  (#set! injection.language "text")
+  (#escape-style! @injection.content {style}))"#
+  ))?;
+  let languages = HashMap::from([(
+    "text".to_string(),
+    LanguageSpec::Formatters(vec!["squeeze".into()]),
+  )]);
+  let formatters = HashMap::from([("squeeze".to_string(), common::shell_formatter(SQUEEZE))]);
+
+  common::format_source(
+    source,
+    "markdown",
+    false,
+    &common::format_context(&grammars, &languages, &formatters),
+  )
+}
+
+fn escaping(style: EscapeStyle, chars: &[&str]) -> Escaping {
+  Escaping::new(style, &chars.iter().map(|char| char.to_string()).collect())
+}
+
+// Escaped text must survive being unescaped and escaped again unchanged
+fn assert_round_trip(escaping: &Escaping, escaped: &str, unescaped: &str) {
+  assert_eq!(escaping.unescape(escaped), unescaped);
+  assert_eq!(escaping.escape(unescaped), escaped);
+  assert_spelled_round_trip(escaping, escaped, unescaped);
+}
+
+// Text spelled other than the scheme would spell it must survive a round trip too, as long as its
+// original spellings are reused
+fn assert_spelled_round_trip(escaping: &Escaping, escaped: &str, unescaped: &str) {
+  let (text, mut spellings) = escaping.unescape_spelled(escaped);
+  assert_eq!(text, unescaped);
+  assert_eq!(escaping.escape_spelled(&text, &mut spellings), escaped);
+}
+
+#[test]
+fn backslash_round_trip() {
+  assert_round_trip(
+    &escaping(EscapeStyle::Backslash, &["\""]),
+    r#"(println \"{:a \\\"hi\\\"}\")"#,
+    r#"(println "{:a \"hi\"}")"#,
+  );
+}
+
+#[test]
+fn doubled_round_trip() {
+  assert_round_trip(
+    &escaping(EscapeStyle::Doubled, &["'"]),
+    "select ''it''''s''",
+    "select 'it''s'",
+  );
+  assert_round_trip(
+    &escaping(EscapeStyle::Doubled, &[]),
+    r#"say ""a """"b"""" c"""#,
+    r#"say "a ""b"" c""#,
+  );
+}
+
+#[test]
+fn json_round_trip() {
+  let json = escaping(EscapeStyle::Json, &[]);
+  assert_round_trip(
+    &json,
+    r#"print(\"a\\n\")\n\tx = \"\\\"\"\u0001"#,
+    "print(\"a\\n\")\n\tx = \"\\\"\"\u{1}",
+  );
+  assert_eq!(
+    json.unescape(r"\u00e9 \ud83d\ude00 \/ \u12"),
+    r"é 😀 / \u12"
+  );
+}
+
+#[test]
+fn html_entities_round_trip() {
+  let html = escaping(EscapeStyle::HtmlEntities, &["\"", "'"]);
+  assert_round_trip(
+    &html,
+    "alert(&quot;a &amp;quot; b&quot;, &#39;c&#39;) &amp;&amp; 1 < 2",
+    "alert(\"a &quot; b\", 'c') && 1 < 2",
+  );
+  assert_eq!(html.unescape("&lt;&#x41;&#66;&unknown;&"), "<AB&unknown;&");
+}
+
+#[test]
+fn percent_round_trip() {
+  let percent = escaping(EscapeStyle::Percent, &[]);
+  assert_round_trip(
+    &percent,
+    "select%20*%20from%20t%20where%20a%20%3D%20'%2520%C3%A9'",
+    "select * from t where a = '%20é'",
+  );
+  assert_round_trip(
+    &escaping(EscapeStyle::Percent, &[" "]),
+    "a%20=%20b%2520",
+    "a = b%20",
+  );
+  assert_eq!(percent.unescape("%zz%"), "%zz%");
+}
+
+#[test]
+fn original_spellings_round_trip() {
+  let html = escaping(EscapeStyle::HtmlEntities, &[]);
+  assert_spelled_round_trip(&html, "a &lt; b", "a < b");
+  assert_spelled_round_trip(&html, "a && b", "a && b");
+  assert_spelled_round_trip(&html, "&quot;a&quot; && \"b\" &amp;", "\"a\" && \"b\" &");
+  assert_spelled_round_trip(&escaping(EscapeStyle::Percent, &[]), "a+b/c:d", "a+b/c:d");
+  assert_spelled_round_trip(&escaping(EscapeStyle::Percent, &[" "]), "a%3Db", "a=b");
+  assert_spelled_round_trip(&escaping(EscapeStyle::Json, &[]), r"caf\u00e9", "café");
+  assert_spelled_round_trip(&escaping(EscapeStyle::Json, &[]), r"a\/b/c", "a/b/c");
+}
+
+#[test]
+fn original_spellings_are_dropped_when_they_would_change_the_text() {
+  let html = escaping(EscapeStyle::HtmlEntities, &[]);
+  let (text, mut spellings) = html.unescape_spelled("a & lt;");
+  assert_eq!(text, "a & lt;");
+  assert_eq!(
+    html.escape_spelled(&text.replace(' ', ""), &mut spellings),
+    "a&amp;lt;"
+  );
+}
+
+#[test]
+fn regions_are_formatted_in_their_escape_style() -> Result<()> {
+  assert_eq!(
+    format_escaped(r#""doubled" "'""#, "```\nselect  ''it''''s''\n```\n")?,
+    "```\nselect ''it''''s''\n```\n"
+  );
+  assert_eq!(
+    format_escaped(r#""json""#, "```\na  =  \\\"b\\\\\\\"\\\"\n```\n")?,
+    "```\na = \\\"b\\\\\\\"\\\"\n```\n"
+  );
+  assert_eq!(
+    format_escaped(
+      r#""html-entities""#,
+      "```\nf(&quot;a  &amp;quot;&quot;)\n```\n"
+    )?,
+    "```\nf(&quot;a &amp;quot;&quot;)\n```\n"
+  );
+  assert_eq!(
+    format_escaped(r#""html-entities""#, "```\na  &&  b &lt; c\n```\n")?,
+    "```\na && b &lt; c\n```\n"
+  );
+  assert_eq!(
+    format_escaped(r#""percent" " ""#, "```\na%20%20%20b%2520\n```\n")?,
+    "```\na%20b%2520\n```\n"
+  );
+
+  Ok(())
+}
+
+// JSON within an HTML attribute, such as a data-* payload
+#[test]
+fn html_attributes_are_formatted_with_jq() -> Result<()> {
+  let grammars = common::fixture_grammars("escape_style")?;
+  let languages = HashMap::from([(
+    "json".to_string(),
+    LanguageSpec::Formatters(vec!["jq".into()]),
+  )]);
+  let formatters = HashMap::from([("jq".to_string(), common::jq())]);
+
+  common::assert_fixture(
+    "escape_style",
+    &common::format_context(&grammars, &languages, &formatters),
+  )
+}
@@ -0,0 +1,3 @@
+((inline) @injection.content
+  (#set! injection.language "text")
+  (#set! injection.combined))
@@ -0,0 +1,3 @@
+((code_fence_content) @injection.content
+  (#set! injection.language "json")
+  (#escape-style! @injection.content "html-entities"))
@@ -0,0 +1,4 @@
+((block_quote) @injection.content
+  (#set! injection.language "json")
+  (#set! injection.include-children)
+  (#strip-prefix! @injection.content "> "))
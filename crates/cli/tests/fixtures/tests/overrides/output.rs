@@ -0,0 +1,4 @@
+fn main() {
+    let message =
+        format!("{} {} {}", "first", "second", "third");
+}
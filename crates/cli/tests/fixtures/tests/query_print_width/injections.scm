@@ -0,0 +1,4 @@
+(fenced_code_block
+  (info_string (language) @injection.language)
+  (code_fence_content) @injection.content
+  (#set! pruner.print-width 60))
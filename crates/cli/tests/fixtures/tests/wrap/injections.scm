@@ -0,0 +1,4 @@
+((code_fence_content) @injection.content
+  (#set! injection.language "rust")
+  (#set! pruner.wrap-prefix "fn main() {\n")
+  (#set! pruner.wrap-suffix "}"))
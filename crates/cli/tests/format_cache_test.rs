@@ -0,0 +1,126 @@
+use anyhow::Result;
+use std::{
+  collections::HashMap,
+  fs,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use pruner::{
+  api::format::{self, FormatOpts},
+  config::{FormatterSpec, LanguageSpec, TempFileLocation, TempFileSpec},
+};
+
+mod common;
+
+#[test]
+fn identical_regions_are_formatted_once() -> Result<()> {
+  let grammars = common::grammars()?;
+  let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+  let calls_file = std::env::temp_dir().join(format!("pruner-format-cache-{nanos}"));
+
+  let formatters = HashMap::from([(
+    "count".to_string(),
+    common::shell_formatter(&format!("echo call >> '{}'; jq .", calls_file.display())),
+  )]);
+  let languages = HashMap::from([(
+    "json".to_string(),
+    LanguageSpec::Formatters(vec!["count".into()]),
+  )]);
+  let context = common::format_context(&grammars, &languages, &formatters);
+
+  // The second time around every region comes from the cache
+  common::assert_fixture("format_cache", &context)?;
+  common::assert_fixture("format_cache", &context)?;
+
+  let calls = fs::read_to_string(&calls_file)?;
+  assert_eq!(calls.lines().count(), 2);
+
+  let _ = fs::remove_file(&calls_file);
+  Ok(())
+}
+
+#[test]
+fn regions_are_cached_per_path_when_formatters_read_adjacent_files() -> Result<()> {
+  let grammars = common::grammars()?;
+
+  let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+  let root = std::env::temp_dir().join(format!("pruner-format-cache-paths-{nanos}"));
+  for dir in ["docs", "migrations"] {
+    fs::create_dir_all(root.join(dir))?;
+    fs::write(root.join(dir).join(".style"), dir)?;
+  }
+
+  // Replaces the region with the contents of the .style file next to it
+  let formatters = HashMap::from([(
+    "style".to_string(),
+    FormatterSpec {
+      stdin: Some(false),
+      temp_file: Some(TempFileSpec::Location(TempFileLocation::Adjacent)),
+      ..common::shell_formatter("cat \"$(dirname '$file')/.style\" > '$file'")
+    },
+  )]);
+  let languages = HashMap::from([(
+    "clojure".to_string(),
+    LanguageSpec::Formatters(vec!["style".into()]),
+  )]);
+  let context = common::format_context(&grammars, &languages, &formatters);
+
+  let source = "```clojure\n(println 1)\n```\n";
+  for dir in ["docs", "migrations"] {
+    let path = root.join(dir).join("README.md");
+    let result = format::format(
+      source.as_bytes(),
+      &FormatOpts {
+        printwidth: 80,
+        language: "markdown",
+        path: Some(&path),
+        indent_width: None,
+        variables: &[],
+      },
+      false,
+      true,
+      &context,
+    )?;
+    assert_eq!(
+      String::from_utf8(result)?,
+      format!("```clojure\n{dir}\n```\n")
+    );
+  }
+
+  let _ = fs::remove_dir_all(&root);
+  Ok(())
+}
+
+#[test]
+fn regions_at_different_depths_share_results() -> Result<()> {
+  let grammars = common::grammars()?;
+  let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+  let calls_file = std::env::temp_dir().join(format!("pruner-format-cache-depths-{nanos}"));
+
+  let formatters = HashMap::from([(
+    "count".to_string(),
+    common::shell_formatter(&format!("echo call >> '{}'; cat", calls_file.display())),
+  )]);
+  let languages = HashMap::from([(
+    "clojure".to_string(),
+    LanguageSpec::Formatters(vec!["count".into()]),
+  )]);
+  let context = common::format_context(&grammars, &languages, &formatters);
+
+  let sources = [
+    "```clojure\n(println 1)\n```\n",
+    "````markdown\n```clojure\n(println 1)\n```\n````\n",
+  ];
+  for source in sources {
+    assert_eq!(
+      common::format_source(source, "markdown", false, &context)?,
+      source
+    );
+  }
+
+  let calls = fs::read_to_string(&calls_file)?;
+  assert_eq!(calls.lines().count(), 1);
+
+  let _ = fs::remove_file(&calls_file);
+  Ok(())
+}
@@ -1,9 +1,6 @@
 use anyhow::Result;
 
-use pruner::{
-  api::format::{self, FormatContext, FormatOpts},
-  wasm::formatter::WasmFormatter,
-};
+use pruner::api::format::{self, FormatOpts};
 
 mod common;
 
@@ -12,7 +9,6 @@ fn format_command() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("format_command/input.clj");
 
@@ -21,15 +17,13 @@ fn format_command() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )
   .unwrap();
 
@@ -45,7 +39,6 @@ fn fail_on_empty_stdout() -> Result<()> {
   let grammars = common::grammars()?;
   let mut formatters = common::formatters();
   let languages = common::languages();
-  let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   formatters.insert(
     "prettier".into(),
@@ -54,6 +47,8 @@ fn fail_on_empty_stdout() -> Result<()> {
       args: vec!["-n".into()],
       stdin: None,
       fail_on_stderr: None,
+      temp_file: None,
+      protocol: None,
     },
   );
 
@@ -64,15 +59,13 @@ fn fail_on_empty_stdout() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   );
 
   match result {
@@ -98,7 +91,6 @@ fn format_escaped() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("format_escaped/input.clj");
 
@@ -107,15 +99,13 @@ fn format_escaped() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )
   .unwrap();
 
@@ -131,7 +121,6 @@ fn markdown_with_escape_characters() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("markdown_with_escape_characters/input.md");
 
@@ -140,15 +129,13 @@ fn markdown_with_escape_characters() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "markdown",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )
   .unwrap();
 
@@ -164,7 +151,6 @@ fn format_double_escaped() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("double_escaped/input.clj");
 
@@ -173,15 +159,13 @@ fn format_double_escaped() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )
   .unwrap();
 
@@ -197,7 +181,6 @@ fn format_injections_only() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("format_injections_only/input.clj");
 
@@ -206,15 +189,13 @@ fn format_injections_only() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     false,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )
   .unwrap();
 
@@ -230,7 +211,6 @@ fn offset_dependent_printwidth() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("offset_dependent_printwidth/input.clj");
 
@@ -239,15 +219,13 @@ fn offset_dependent_printwidth() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     false,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )
   .unwrap();
 
@@ -263,7 +241,6 @@ fn format_fixes_indent() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("format_fixes_indent/input.clj");
 
@@ -272,15 +249,13 @@ fn format_fixes_indent() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )
   .unwrap();
 
@@ -296,7 +271,6 @@ fn markdown_with_html() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("markdown_with_html/input.md");
 
@@ -305,15 +279,13 @@ fn markdown_with_html() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "markdown",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )
   .unwrap();
 
@@ -329,7 +301,6 @@ fn utf8_docstring() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let source = common::load_file("utf8_docstring/input.clj");
 
@@ -338,15 +309,13 @@ fn utf8_docstring() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     true,
     true,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )
   .unwrap();
 
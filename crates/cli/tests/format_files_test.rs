@@ -6,10 +6,7 @@ use std::{
   time::{SystemTime, UNIX_EPOCH},
 };
 
-use pruner::{
-  api::format::{self, FormatContext, FormatOpts},
-  wasm::formatter::WasmFormatter,
-};
+use pruner::api::format::{self, FormatOpts};
 
 mod common;
 
@@ -18,7 +15,6 @@ fn format_files() -> Result<()> {
   let grammars = common::grammars()?;
   let formatters = common::formatters();
   let languages = common::languages();
-  let wasm_formatter = WasmFormatter::new("cache".into())?;
 
   let input_dir = PathBuf::from("tests/fixtures/tests/format_files/input");
   let output_dir = PathBuf::from("tests/fixtures/tests/format_files/output");
@@ -34,14 +30,12 @@ fn format_files() -> Result<()> {
     &FormatOpts {
       printwidth: 80,
       language: "clojure",
+      path: None,
+      indent_width: None,
+      variables: &[],
     },
     false,
-    &FormatContext {
-      grammars: &grammars,
-      languages: &languages,
-      formatters: &formatters,
-      wasm_formatter: &wasm_formatter,
-    },
+    &common::format_context(&grammars, &languages, &formatters),
   )?;
 
   let actual_files = collect_files(&temp_dir)?;
@@ -0,0 +1,125 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::format::FormatContext,
+  config::{FormatOrder, LanguageSpec},
+};
+
+mod common;
+
+const SOURCE: &str = "todo\n\n```sql\nselect 1\n```\n";
+
+// Marks the document as done once its sql regions have been formatted, so the root depends on the
+// result of its injections.
+const MARK_DONE: &str = r#"input=$(cat); case "$input" in
+  *SELECT*) printf '%s\n' "$input" | sed s/todo/done/ ;;
+  *) printf '%s\n' "$input" ;;
+esac"#;
+
+fn format_passes(
+  root_script: &str,
+  max_passes: usize,
+  format_order: FormatOrder,
+  source: &str,
+) -> Result<String> {
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([
+    (
+      "markdown".to_string(),
+      LanguageSpec::Formatters(vec!["root".into()]),
+    ),
+    (
+      "sql".to_string(),
+      LanguageSpec::Formatters(vec!["upper".into()]),
+    ),
+  ]);
+  let formatters = HashMap::from([
+    ("root".to_string(), common::shell_formatter(root_script)),
+    ("upper".to_string(), common::shell_formatter("tr a-z A-Z")),
+  ]);
+
+  common::format_source(
+    source,
+    "markdown",
+    true,
+    &FormatContext {
+      max_passes,
+      format_order,
+      ..common::format_context(&grammars, &languages, &formatters)
+    },
+  )
+}
+
+#[test]
+fn single_pass_runs_the_root_before_injections() -> Result<()> {
+  let result = format_passes(MARK_DONE, 1, FormatOrder::RootFirst, SOURCE)?;
+  assert_eq!(result, "todo\n\n```sql\nSELECT 1\n```\n");
+
+  Ok(())
+}
+
+#[test]
+fn passes_repeat_until_the_result_stops_changing() -> Result<()> {
+  let result = format_passes(MARK_DONE, 5, FormatOrder::RootFirst, SOURCE)?;
+  assert_eq!(result, "done\n\n```sql\nSELECT 1\n```\n");
+
+  Ok(())
+}
+
+#[test]
+fn injections_can_be_formatted_first() -> Result<()> {
+  let result = format_passes(MARK_DONE, 1, FormatOrder::InjectionsFirst, SOURCE)?;
+  assert_eq!(result, "done\n\n```sql\nSELECT 1\n```\n");
+
+  Ok(())
+}
+
+#[test]
+fn passes_stop_at_the_limit() -> Result<()> {
+  let result = format_passes("cat; echo x", 3, FormatOrder::RootFirst, "text\n")?;
+  assert_eq!(result, "text\nx\nx\nx\n");
+
+  Ok(())
+}
+
+#[test]
+fn injections_first_pass_is_not_counted() -> Result<()> {
+  let result = format_passes("cat; echo x", 2, FormatOrder::InjectionsFirst, "text\n")?;
+  assert_eq!(result, "text\nx\nx\n");
+
+  Ok(())
+}
+
+#[test]
+fn later_passes_see_regions_formatted_by_jq() -> Result<()> {
+  // Marks the document as done once jq has expanded its json regions
+  let mark_done = r#"input=$(cat); case "$input" in
+  *'  "id": 1'*) printf '%s\n' "$input" | sed s/todo/done/ ;;
+  *) printf '%s\n' "$input" ;;
+esac"#;
+
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([
+    (
+      "markdown".to_string(),
+      LanguageSpec::Formatters(vec!["root".into()]),
+    ),
+    (
+      "json".to_string(),
+      LanguageSpec::Formatters(vec!["jq".into()]),
+    ),
+  ]);
+  let formatters = HashMap::from([
+    ("root".to_string(), common::shell_formatter(mark_done)),
+    ("jq".to_string(), common::jq()),
+  ]);
+
+  common::assert_fixture(
+    "format_passes",
+    &FormatContext {
+      max_passes: 3,
+      ..common::format_context(&grammars, &languages, &formatters)
+    },
+  )
+}
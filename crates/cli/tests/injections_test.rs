@@ -1,8 +1,15 @@
 use anyhow::Result;
-use std::collections::HashSet;
-use tree_sitter::{Point, Range};
+use std::collections::{HashMap, HashSet};
+use tree_sitter::{Point, Query, Range};
 
-use pruner::api::injections::{self, InjectedRegion, InjectionOpts};
+use pruner::{
+  api::{
+    format::{self, FormatOpts},
+    grammar::Grammar,
+    injections::{self, InjectedRegion, InjectionOpts},
+  },
+  config::LanguageSpec,
+};
 
 mod common;
 
@@ -27,7 +34,7 @@ fn injected_regions_markdown() -> Result<()> {
 
   let mut parser = tree_sitter::Parser::new();
   let injected_regions =
-    injections::extract_language_injections(&mut parser, grammar, source_bytes)?;
+    injections::extract_language_injections(&mut parser, grammar, source_bytes, None)?;
 
   assert_eq!(
     injected_regions,
@@ -40,8 +47,17 @@ fn injected_regions_markdown() -> Result<()> {
       },
       lang: "markdown".into(),
       opts: InjectionOpts {
-        escape_chars: HashSet::from(["\"".to_string()])
-      }
+        escape_chars: HashSet::from(["\"".to_string()]),
+        captures: vec!["def-name".into(), "def-type".into(), "docstring".into()],
+        ..Default::default()
+      },
+      parts: vec![Range {
+        start_byte: 32,
+        end_byte: 109,
+        start_point: Point { row: 1, column: 3 },
+        end_point: Point { row: 6, column: 6 }
+      }],
+      nesting: 0,
     }]
   );
 
@@ -71,7 +87,7 @@ fn injected_regions_newline() -> Result<()> {
 
   let mut parser = tree_sitter::Parser::new();
   let injected_regions =
-    injections::extract_language_injections(&mut parser, grammar, source_bytes)?;
+    injections::extract_language_injections(&mut parser, grammar, source_bytes, None)?;
 
   assert_eq!(
     injected_regions,
@@ -85,8 +101,17 @@ fn injected_regions_newline() -> Result<()> {
         },
         lang: "markdown_inline".into(),
         opts: InjectionOpts {
-          escape_chars: HashSet::default()
-        }
+          escape_chars: HashSet::default(),
+          captures: Vec::new(),
+          ..Default::default()
+        },
+        parts: vec![Range {
+          start_byte: 0,
+          end_byte: 5,
+          start_point: Point { row: 0, column: 0 },
+          end_point: Point { row: 0, column: 5 }
+        }],
+        nesting: 0,
       },
       InjectedRegion {
         range: Range {
@@ -97,8 +122,17 @@ fn injected_regions_newline() -> Result<()> {
         },
         lang: "clojure".into(),
         opts: InjectionOpts {
-          escape_chars: HashSet::default()
-        }
+          escape_chars: HashSet::default(),
+          captures: Vec::new(),
+          ..Default::default()
+        },
+        parts: vec![Range {
+          start_byte: 21,
+          end_byte: 52,
+          start_point: Point { row: 3, column: 0 },
+          end_point: Point { row: 5, column: 0 }
+        }],
+        nesting: 0,
       }
     ],
     "The clojure injected region should not contain the trailing ``` characters"
@@ -106,3 +140,287 @@ fn injected_regions_newline() -> Result<()> {
 
   Ok(())
 }
+
+fn markdown_with_injections(query: &str) -> Result<Grammar> {
+  let grammars = common::grammars()?;
+  let markdown = grammars
+    .get("markdown")
+    .ok_or_else(|| anyhow::anyhow!("Missing markdown grammar"))?;
+
+  Ok(Grammar {
+    name: "markdown".into(),
+    lang: markdown.lang.clone(),
+    injections: Query::new(&markdown.lang, query)?,
+    file_types: Vec::new(),
+    injection_regex: None,
+  })
+}
+
+fn region_langs(
+  grammar: &Grammar,
+  source: &str,
+  parent_language: Option<&str>,
+) -> Result<Vec<(String, String)>> {
+  let mut parser = tree_sitter::Parser::new();
+  let regions = injections::extract_language_injections(
+    &mut parser,
+    grammar,
+    source.as_bytes(),
+    parent_language,
+  )?;
+  Ok(
+    regions
+      .into_iter()
+      .map(|region| {
+        let text = source[region.range.start_byte..region.range.end_byte].to_string();
+        (region.lang, text)
+      })
+      .collect(),
+  )
+}
+
+#[test]
+fn lua_match_and_gsub_directives() -> Result<()> {
+  let grammar = markdown_with_injections(
+    r#"(fenced_code_block
+  (info_string (language) @injection.language)
+  (code_fence_content) @injection.content
+  (#lua-match? @injection.language "^%a+%-?%d*$")
+  (#not-lua-match? @injection.language "^ignored")
+  (#gsub! @injection.language "^(%a+)%-%d+$" "%1"))"#,
+  )?;
+
+  let source = "```sql-2\nselect 1\n```\n\n```c++\nint x;\n```\n\n```ignored\nx\n```\n";
+  assert_eq!(
+    region_langs(&grammar, source, None)?,
+    vec![("sql".to_string(), "select 1\n".to_string())]
+  );
+
+  Ok(())
+}
+
+#[test]
+fn trim_directive() -> Result<()> {
+  let source = "```\n\n  select 1  \n\n```\n";
+
+  let grammar = markdown_with_injections(
+    r#"((code_fence_content) @injection.content
+  (#set! injection.language "sql")
+  (#trim! @injection.content))"#,
+  )?;
+  assert_eq!(
+    region_langs(&grammar, source, None)?,
+    vec![("sql".to_string(), "\n  select 1  ".to_string())]
+  );
+
+  let grammar = markdown_with_injections(
+    r#"((code_fence_content) @injection.content
+  (#set! injection.language "sql")
+  (#trim! @injection.content 1 1 1 1))"#,
+  )?;
+  assert_eq!(
+    region_langs(&grammar, source, None)?,
+    vec![("sql".to_string(), "select 1".to_string())]
+  );
+
+  let grammar = markdown_with_injections(
+    r#"((code_fence_content) @injection.content
+  (#set! injection.language "sql")
+  (#trim! @injection.content 1 0 0 0))"#,
+  )?;
+  assert_eq!(
+    region_langs(&grammar, source, None)?,
+    vec![("sql".to_string(), "  select 1  \n\n".to_string())]
+  );
+
+  Ok(())
+}
+
+#[test]
+fn self_and_parent_injections() -> Result<()> {
+  let source = "Some text\n";
+
+  let grammar =
+    markdown_with_injections(r#"((inline) @injection.content (#set! injection.self))"#)?;
+  assert_eq!(
+    region_langs(&grammar, source, Some("clojure"))?,
+    vec![("markdown".to_string(), "Some text".to_string())]
+  );
+
+  let grammar =
+    markdown_with_injections(r#"((inline) @injection.content (#set! injection.parent))"#)?;
+  assert_eq!(
+    region_langs(&grammar, source, Some("clojure"))?,
+    vec![("clojure".to_string(), "Some text".to_string())]
+  );
+  assert_eq!(
+    region_langs(&grammar, source, None)?,
+    vec![("markdown".to_string(), "Some text".to_string())]
+  );
+
+  Ok(())
+}
+
+#[test]
+fn children_are_only_included_when_requested() -> Result<()> {
+  let source = "Some text\n";
+
+  let grammar = markdown_with_injections(
+    r#"((paragraph) @injection.content (#set! injection.language "text"))"#,
+  )?;
+  assert_eq!(region_langs(&grammar, source, None)?, vec![]);
+
+  let grammar = markdown_with_injections(
+    r#"((paragraph) @injection.content
+  (#set! injection.language "text")
+  (#set! injection.include-children))"#,
+  )?;
+  assert_eq!(
+    region_langs(&grammar, source, None)?,
+    vec![("text".to_string(), "Some text\n".to_string())]
+  );
+
+  Ok(())
+}
+
+#[test]
+fn children_of_the_content_are_cut_out_of_the_region() -> Result<()> {
+  let source = "> ```sql\n> select 1\n> select 2\n> ```\n";
+
+  let grammar = markdown_with_injections(
+    r#"(fenced_code_block
+  (info_string (language) @injection.language)
+  (code_fence_content) @injection.content)"#,
+  )?;
+  let regions = injections::extract_language_injections(
+    &mut tree_sitter::Parser::new(),
+    &grammar,
+    source.as_bytes(),
+    None,
+  )?;
+  assert_eq!(
+    regions
+      .iter()
+      .map(|region| {
+        region
+          .parts
+          .iter()
+          .map(|part| &source[part.start_byte..part.end_byte])
+          .collect::<Vec<_>>()
+      })
+      .collect::<Vec<_>>(),
+    vec![vec!["select 1\n", "select 2\n"]]
+  );
+
+  // The block continuations of the quote are kept as they are
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([(
+    "sql".to_string(),
+    LanguageSpec::Formatters(vec!["upper".into()]),
+  )]);
+  let formatters = HashMap::from([("upper".to_string(), common::shell_formatter("tr a-z A-Z"))]);
+  let result = format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language: "markdown",
+      path: None,
+      indent_width: None,
+      variables: &[],
+    },
+    false,
+    true,
+    &common::format_context(&grammars, &languages, &formatters),
+  )?;
+  assert_eq!(
+    String::from_utf8(result)?,
+    "> ```sql\n> SELECT 1\n> SELECT 2\n> ```\n"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn out_of_bounds_offsets_are_skipped() -> Result<()> {
+  let source = "Some text\n";
+
+  for offset in ["0 -20 0 0", "0 0 5 0", "0 5 0 -5"] {
+    let grammar = markdown_with_injections(&format!(
+      r#"((inline) @injection.content
+  (#set! injection.language "text")
+  (#offset! @injection.content {offset}))"#
+    ))?;
+    assert_eq!(region_langs(&grammar, source, None)?, vec![], "{offset}");
+  }
+
+  Ok(())
+}
+
+#[test]
+fn overlapping_regions_are_resolved() -> Result<()> {
+  let source = "```sql\nselect 1\n```\n";
+  let sql = ("sql".to_string(), "select 1\n".to_string());
+
+  let grammar = markdown_with_injections(
+    r#"((code_fence_content) @injection.content (#set! injection.language "sql"))
+((code_fence_content) @injection.content (#set! injection.language "sql"))"#,
+  )?;
+  assert_eq!(region_langs(&grammar, source, None)?, vec![sql.clone()]);
+
+  let grammar = markdown_with_injections(
+    r#"((code_fence_content) @injection.content (#set! injection.language "sql"))
+((code_fence_content) @injection.content (#set! injection.language "text"))"#,
+  )?;
+  assert_eq!(region_langs(&grammar, source, None)?, vec![sql.clone()]);
+
+  let grammar = markdown_with_injections(
+    r#"((code_fence_content) @injection.content (#set! injection.language "sql"))
+((code_fence_content) @injection.content
+  (#set! injection.language "text")
+  (#set! priority 10))"#,
+  )?;
+  assert_eq!(
+    region_langs(&grammar, source, None)?,
+    vec![("text".to_string(), "select 1\n".to_string())]
+  );
+
+  // Nested regions are kept, along with how deeply they are nested
+  let grammar = markdown_with_injections(
+    r#"((code_fence_content) @injection.content (#set! injection.language "sql"))
+((fenced_code_block) @injection.content
+  (#set! injection.language "markdown")
+  (#set! injection.include-children))"#,
+  )?;
+  assert_eq!(
+    region_langs(&grammar, source, None)?,
+    vec![("markdown".to_string(), source.to_string()), sql.clone()]
+  );
+  let regions = injections::extract_language_injections(
+    &mut tree_sitter::Parser::new(),
+    &grammar,
+    source.as_bytes(),
+    None,
+  )?;
+  assert_eq!(
+    regions
+      .iter()
+      .map(|region| (region.lang.as_str(), region.nesting))
+      .collect::<Vec<_>>(),
+    vec![("markdown", 0), ("sql", 1)]
+  );
+
+  let grammar = markdown_with_injections(
+    r#"((inline) @injection.content
+  (#set! injection.language "a")
+  (#offset! @injection.content 0 2 0 0))
+((inline) @injection.content
+  (#set! injection.language "b")
+  (#offset! @injection.content 0 0 0 -2))"#,
+  )?;
+  assert_eq!(
+    region_langs(&grammar, "Some text\n", None)?,
+    vec![("a".to_string(), "me text".to_string())]
+  );
+
+  Ok(())
+}
@@ -0,0 +1,123 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::config::{LanguageFormatters, LanguageSettings, LanguageSpec};
+
+mod common;
+
+fn format_markdown(source: &str, languages: &LanguageFormatters) -> Result<String> {
+  let grammars = common::grammars()?;
+  let formatters = HashMap::from([(
+    "width".to_string(),
+    common::shell_formatter("cat > /dev/null; printf '%s:%s' $textwidth $indentwidth"),
+  )]);
+
+  common::format_source(
+    source,
+    "markdown",
+    true,
+    &common::format_context(&grammars, languages, &formatters),
+  )
+}
+
+fn table(settings: LanguageSettings) -> LanguageSpec {
+  LanguageSpec::Table {
+    formatters: vec!["width".into()],
+    settings,
+  }
+}
+
+#[test]
+fn language_settings_apply_to_injected_regions() -> Result<()> {
+  let source = "# Title\n\n```sql\nselect 1\n```\n\n```text\nhello\n```\n";
+
+  let mut languages = HashMap::from([
+    (
+      "markdown".to_string(),
+      LanguageSpec::Table {
+        formatters: vec![],
+        settings: LanguageSettings {
+          print_width: Some(60),
+          ..Default::default()
+        },
+      },
+    ),
+    (
+      "sql".to_string(),
+      table(LanguageSettings {
+        print_width: Some(100),
+        indent_width: Some(4),
+        ..Default::default()
+      }),
+    ),
+    (
+      "text".to_string(),
+      LanguageSpec::Formatters(vec!["width".into()]),
+    ),
+  ]);
+
+  assert_eq!(
+    format_markdown(source, &languages)?,
+    "# Title\n\n```sql\n100:4\n```\n\n```text\n60:2\n```\n"
+  );
+
+  languages.insert(
+    "sql".to_string(),
+    table(LanguageSettings {
+      print_width: Some(100),
+      max_print_width: Some(50),
+      ..Default::default()
+    }),
+  );
+  languages.insert(
+    "text".to_string(),
+    table(LanguageSettings {
+      min_print_width: Some(70),
+      ..Default::default()
+    }),
+  );
+
+  assert_eq!(
+    format_markdown(source, &languages)?,
+    "# Title\n\n```sql\n50:2\n```\n\n```text\n70:2\n```\n"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn language_print_width_is_passed_to_formatters() -> Result<()> {
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([(
+    "rust".to_string(),
+    LanguageSpec::Table {
+      formatters: vec!["rustfmt".into()],
+      settings: LanguageSettings {
+        print_width: Some(60),
+        ..Default::default()
+      },
+    },
+  )]);
+  let formatters = HashMap::from([("rustfmt".to_string(), common::rustfmt())]);
+
+  common::assert_fixture(
+    "language_settings",
+    &common::format_context(&grammars, &languages, &formatters),
+  )
+}
+
+#[test]
+fn print_width_is_reduced_by_column() {
+  let settings = LanguageSettings {
+    print_width: Some(100),
+    min_print_width: Some(40),
+    ..Default::default()
+  };
+  assert_eq!(settings.print_width(80, 0), 100);
+  assert_eq!(settings.print_width(80, 20), 80);
+  assert_eq!(settings.print_width(80, 90), 40);
+
+  let settings = LanguageSettings::default();
+  assert_eq!(settings.print_width(80, 20), 80);
+  assert_eq!(settings.print_width(0, 0), 1);
+}
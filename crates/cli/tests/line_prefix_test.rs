@@ -0,0 +1,73 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::config::LanguageSpec;
+
+mod common;
+
+fn format_prefixed(script: &str, source: &str) -> Result<String> {
+  let grammars = common::grammars_with_markdown_query(
+    r#"((block_quote) @injection.content
+  (#set! injection.language "text")
+  (#set! injection.include-children)
+  (#strip-prefix! @injection.content "> "))"#,
+  )?;
+  let languages = HashMap::from([(
+    "text".to_string(),
+    LanguageSpec::Formatters(vec!["script".into()]),
+  )]);
+  let formatters = HashMap::from([("script".to_string(), common::shell_formatter(script))]);
+
+  common::format_source(
+    source,
+    "markdown",
+    false,
+    &common::format_context(&grammars, &languages, &formatters),
+  )
+}
+
+// Rejects quote markers, and squeezes repeated spaces
+const STRICT: &str = r#"input=$(cat)
+case "$input" in
+  *'>'*) echo "unexpected quote marker" >&2; exit 1 ;;
+esac
+printf '%s\n' "$input" | tr -s ' '"#;
+
+#[test]
+fn line_prefix_is_stripped_while_formatting() -> Result<()> {
+  let result = format_prefixed(STRICT, "> hello   world\n>\n> second   line\n")?;
+  assert_eq!(result, "> hello world\n>\n> second line\n");
+
+  Ok(())
+}
+
+#[test]
+fn line_prefix_is_restored_on_indented_lines() -> Result<()> {
+  let result = format_prefixed(STRICT, "- item\n\n  > hello   world\n  > more\n")?;
+  assert_eq!(result, "- item\n\n  > hello world\n  > more\n");
+
+  Ok(())
+}
+
+#[test]
+fn line_prefix_is_added_to_new_lines() -> Result<()> {
+  let grammars = common::fixture_grammars("line_prefix")?;
+  let languages = HashMap::from([(
+    "json".to_string(),
+    LanguageSpec::Formatters(vec!["jq".into()]),
+  )]);
+  let formatters = HashMap::from([("jq".to_string(), common::jq())]);
+
+  common::assert_fixture(
+    "line_prefix",
+    &common::format_context(&grammars, &languages, &formatters),
+  )
+}
+
+#[test]
+fn line_prefix_reduces_the_print_width() -> Result<()> {
+  let result = format_prefixed("cat >/dev/null; echo $textwidth", "- item\n\n  > text\n")?;
+  assert_eq!(result, "- item\n\n  > 76\n");
+
+  Ok(())
+}
@@ -0,0 +1,101 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::format::{FormatContext, FormatWarning},
+  config::{LanguageSpec, OnError},
+};
+
+mod common;
+
+const NESTED: &str = "````markdown\n```sql\nselect 1\n```\n````\n";
+
+fn format_nested(
+  query: Option<&str>,
+  max_injection_depth: usize,
+  source: &str,
+) -> Result<(String, Vec<FormatWarning>)> {
+  let grammars = match query {
+    Some(query) => common::grammars_with_markdown_query(query)?,
+    None => common::grammars()?,
+  };
+  let languages = HashMap::from([(
+    "sql".to_string(),
+    LanguageSpec::Formatters(vec!["upper".into()]),
+  )]);
+  let formatters = HashMap::from([("upper".to_string(), common::shell_formatter("tr a-z A-Z"))]);
+
+  let context = FormatContext {
+    on_error: OnError::Skip,
+    max_injection_depth,
+    ..common::format_context(&grammars, &languages, &formatters)
+  };
+  let result = common::format_source(source, "markdown", false, &context)?;
+
+  Ok((result, context.warnings.take()))
+}
+
+#[test]
+fn regions_within_the_depth_limit_are_formatted() -> Result<()> {
+  let (result, warnings) = format_nested(None, 2, NESTED)?;
+  assert_eq!(result, "````markdown\n```sql\nSELECT 1\n```\n````\n");
+  assert!(warnings.is_empty());
+
+  Ok(())
+}
+
+#[test]
+fn nested_regions_are_formatted_with_jq() -> Result<()> {
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([(
+    "json".to_string(),
+    LanguageSpec::Formatters(vec!["jq".into()]),
+  )]);
+  let formatters = HashMap::from([("jq".to_string(), common::jq())]);
+
+  common::assert_fixture(
+    "nesting",
+    &common::format_context(&grammars, &languages, &formatters),
+  )
+}
+
+#[test]
+fn regions_beyond_the_depth_limit_fail() -> Result<()> {
+  let (result, warnings) = format_nested(None, 1, NESTED)?;
+  assert_eq!(result, NESTED);
+  assert_eq!(warnings.len(), 1);
+  assert_eq!(warnings[0].language, "sql");
+  assert!(
+    warnings[0]
+      .message
+      .contains("nested 2 levels deep, more than the max_injection_depth of 1"),
+    "unexpected warning: {}",
+    warnings[0].message
+  );
+
+  Ok(())
+}
+
+#[test]
+fn self_injecting_queries_are_detected() -> Result<()> {
+  let source = "Some text\n";
+  let (result, warnings) = format_nested(
+    Some(
+      r#"((document) @injection.content
+  (#set! injection.self)
+  (#set! injection.include-children))"#,
+    ),
+    pruner::config::DEFAULT_MAX_INJECTION_DEPTH,
+    source,
+  )?;
+  assert_eq!(result, source);
+
+  assert_eq!(warnings.len(), 1);
+  assert!(
+    warnings[0].message.contains("Injection cycle"),
+    "unexpected warning: {}",
+    warnings[0].message
+  );
+
+  Ok(())
+}
@@ -0,0 +1,114 @@
+use anyhow::Result;
+use std::{collections::HashMap, path::Path};
+
+use pruner::{
+  api::format::{self, FormatContext, FormatOpts, FormatWarning, WarningKind},
+  config::{LanguageSpec, OnError},
+};
+
+mod common;
+
+fn format_document(
+  source: &str,
+  language: &str,
+  on_error: OnError,
+) -> Result<(String, Vec<FormatWarning>)> {
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([(
+    "json".to_string(),
+    LanguageSpec::Formatters(vec!["jq".into()]),
+  )]);
+  let formatters = HashMap::from([("jq".to_string(), common::jq())]);
+
+  let context = FormatContext {
+    on_error,
+    ..common::format_context(&grammars, &languages, &formatters)
+  };
+  let result = format::format(
+    source.as_bytes(),
+    &FormatOpts {
+      printwidth: 80,
+      language,
+      path: Some(Path::new("docs/queries.md")),
+      indent_width: None,
+      variables: &[],
+    },
+    true,
+    true,
+    &context,
+  )?;
+
+  Ok((String::from_utf8(result)?, context.warnings.take()))
+}
+
+#[test]
+fn failing_regions_fail_the_document() {
+  let source = common::load_file("on_error/input.md");
+  let err = format_document(&source, "markdown", OnError::Fail)
+    .expect_err("the document should fail to format");
+  assert!(
+    format!("{err:#}").contains("parse error"),
+    "unexpected error: {err:#}"
+  );
+}
+
+#[test]
+fn failing_regions_are_skipped() -> Result<()> {
+  let source = common::load_file("on_error/input.md");
+  let (result, warnings) = format_document(&source, "markdown", OnError::Skip)?;
+  assert_eq!(result, common::load_file("on_error/output.md"));
+
+  assert_eq!(warnings.len(), 1);
+  assert_eq!(
+    warnings[0].path.as_deref(),
+    Some(Path::new("docs/queries.md"))
+  );
+  assert_eq!(warnings[0].line, 8);
+  assert_eq!(warnings[0].language, "json");
+  assert_eq!(warnings[0].kind, WarningKind::Failed);
+  assert!(warnings[0].message.contains("parse error"));
+
+  Ok(())
+}
+
+#[test]
+fn failing_root_is_skipped() -> Result<()> {
+  let (result, warnings) = format_document("{bad\n", "json", OnError::Skip)?;
+  assert_eq!(result, "{bad\n");
+
+  assert_eq!(
+    warnings
+      .iter()
+      .map(|warning| (warning.line, warning.language.as_str()))
+      .collect::<Vec<_>>(),
+    vec![(1, "json")]
+  );
+
+  Ok(())
+}
+
+#[test]
+fn warnings_display_their_location() {
+  let warning = FormatWarning {
+    kind: WarningKind::Failed,
+    path: Some("docs/queries.md".into()),
+    line: 8,
+    language: "sql".into(),
+    message: "syntax error".into(),
+  };
+  assert_eq!(
+    warning.to_string(),
+    "Failed to format region docs/queries.md:8 [sql] syntax error"
+  );
+
+  // Other kinds of warnings aren't failures of the formatter, their message says what happened
+  let warning = FormatWarning {
+    kind: WarningKind::Rejected,
+    message: "Rejected the formatted region, in [markdown] it doesn't parse".into(),
+    ..warning
+  };
+  assert_eq!(
+    warning.to_string(),
+    "docs/queries.md:8 [sql] Rejected the formatted region, in [markdown] it doesn't parse"
+  );
+}
@@ -0,0 +1,255 @@
+use anyhow::Result;
+use std::{
+  collections::HashMap,
+  fs::{self, File},
+  io::Write,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use pruner::{
+  api::{
+    format::{self, FormatContext, FormatOpts},
+    overrides::Overrides,
+  },
+  config::{ConfigFile, LanguageSpec, OverrideConfig},
+};
+
+mod common;
+
+fn unique_temp_dir() -> PathBuf {
+  let nanos = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .expect("time should be available")
+    .as_nanos();
+  let temp_dir = std::env::temp_dir().join(format!("pruner-test-{nanos}"));
+  fs::create_dir_all(&temp_dir).expect("should create temp dir");
+  temp_dir
+}
+
+fn format_path(
+  source: &str,
+  language: &str,
+  path: &Path,
+  overrides: &[OverrideConfig],
+) -> Result<String> {
+  let grammars = HashMap::new();
+  let languages = HashMap::from([(
+    "sql".to_string(),
+    LanguageSpec::Formatters(vec!["upper".into()]),
+  )]);
+  let formatters = HashMap::from([
+    ("upper".to_string(), common::shell_formatter("tr a-z A-Z")),
+    (
+      "width".to_string(),
+      common::shell_formatter("cat > /dev/null; printf 'width=%s' $textwidth"),
+    ),
+    ("rustfmt".to_string(), common::rustfmt()),
+  ]);
+  let overrides = Overrides::new(Path::new("/repo"), overrides)?;
+
+  let result = format::format_document(
+    source.as_bytes(),
+    path,
+    &FormatOpts {
+      printwidth: 80,
+      language,
+      path: None,
+      indent_width: None,
+      variables: &[],
+    },
+    true,
+    &FormatContext {
+      overrides: Arc::new(overrides),
+      ..common::format_context(&grammars, &languages, &formatters)
+    },
+  )?;
+
+  Ok(String::from_utf8(result)?)
+}
+
+#[test]
+fn overrides_apply_to_matching_paths() -> Result<()> {
+  let overrides = vec![
+    OverrideConfig {
+      files: vec!["migrations/**/*.sql".into()],
+      exclude: vec!["migrations/legacy/**".into()],
+      print_width: Some(100),
+      languages: Some(HashMap::from([(
+        "sql".to_string(),
+        LanguageSpec::Formatters(vec!["width".into()]),
+      )])),
+      ..Default::default()
+    },
+    OverrideConfig {
+      files: vec!["vendor/**".into()],
+      skip: Some(true),
+      ..Default::default()
+    },
+  ];
+
+  assert_eq!(
+    format_path(
+      "select 1",
+      "sql",
+      Path::new("/repo/src/query.sql"),
+      &overrides
+    )?,
+    "SELECT 1"
+  );
+  assert_eq!(
+    format_path(
+      "select 1",
+      "sql",
+      Path::new("/repo/migrations/2024/01.sql"),
+      &overrides
+    )?,
+    "width=100"
+  );
+  assert_eq!(
+    format_path(
+      "select 1",
+      "sql",
+      Path::new("migrations/2024/01.sql"),
+      &overrides
+    )?,
+    "width=100"
+  );
+  assert_eq!(
+    format_path(
+      "select 1",
+      "sql",
+      Path::new("/repo/migrations/legacy/01.sql"),
+      &overrides
+    )?,
+    "SELECT 1"
+  );
+  assert_eq!(
+    format_path(
+      "select 1",
+      "sql",
+      Path::new("/repo/vendor/lib/query.sql"),
+      &overrides
+    )?,
+    "select 1"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn overrides_are_applied_in_order() -> Result<()> {
+  let overrides = vec![
+    OverrideConfig {
+      files: vec!["**/*.rs".into()],
+      print_width: Some(100),
+      languages: Some(HashMap::from([(
+        "rust".to_string(),
+        LanguageSpec::Formatters(vec!["rustfmt".into()]),
+      )])),
+      ..Default::default()
+    },
+    OverrideConfig {
+      files: vec!["docs/**".into()],
+      print_width: Some(60),
+      ..Default::default()
+    },
+  ];
+
+  let source = common::load_file("overrides/input.rs");
+  assert_eq!(
+    format_path(&source, "rust", Path::new("/repo/docs/main.rs"), &overrides)?,
+    common::load_file("overrides/output.rs")
+  );
+  assert_eq!(
+    format_path(&source, "rust", Path::new("/repo/main.rs"), &overrides)?,
+    source
+  );
+
+  Ok(())
+}
+
+#[test]
+fn files_matching_the_same_overrides_share_their_settings() -> Result<()> {
+  let overrides = Overrides::new(
+    Path::new("/repo"),
+    &[OverrideConfig {
+      files: vec!["migrations/**".into()],
+      formatters: Some(HashMap::from([(
+        "upper".to_string(),
+        common::shell_formatter("cat"),
+      )])),
+      ..Default::default()
+    }],
+  )?;
+  let languages = HashMap::new();
+  let formatters = HashMap::new();
+
+  let first = overrides.resolve(
+    Path::new("/repo/migrations/01.sql"),
+    &languages,
+    &formatters,
+  );
+  let second = overrides.resolve(
+    Path::new("/repo/migrations/02.sql"),
+    &languages,
+    &formatters,
+  );
+  let other = overrides.resolve(Path::new("/repo/src/query.sql"), &languages, &formatters);
+
+  assert!(Arc::ptr_eq(&first, &second));
+  assert!(first.formatters.is_some());
+  assert!(!Arc::ptr_eq(&first, &other));
+  assert!(other.formatters.is_none());
+
+  Ok(())
+}
+
+#[test]
+fn loads_overrides_from_toml() {
+  let temp_dir = unique_temp_dir();
+  let config_path = temp_dir.join("config.toml");
+
+  let mut file = File::create(&config_path).expect("should create config file");
+  writeln!(
+    file,
+    r#"
+[[overrides]]
+files = ["migrations/**/*.sql"]
+print_width = 100
+
+[overrides.languages]
+sql = ["sqlfluff"]
+
+[[overrides]]
+files = ["vendor/**"]
+exclude = ["vendor/ours/**"]
+skip = true
+"#
+  )
+  .expect("should write config file");
+
+  let config = ConfigFile::from_file(&config_path).expect("should load config");
+
+  assert_eq!(
+    config.overrides.expect("overrides should be set"),
+    vec![
+      OverrideConfig {
+        files: vec!["migrations/**/*.sql".into()],
+        print_width: Some(100),
+        languages: Some(HashMap::from([(
+          "sql".to_string(),
+          LanguageSpec::Formatters(vec!["sqlfluff".into()])
+        )])),
+        ..Default::default()
+      },
+      OverrideConfig {
+        files: vec!["vendor/**".into()],
+        exclude: vec!["vendor/ours/**".into()],
+        skip: Some(true),
+        ..Default::default()
+      },
+    ]
+  );
+}
@@ -0,0 +1,112 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::config::{LanguageSettings, LanguageSpec};
+
+mod common;
+
+fn format_markdown(
+  query: Option<&str>,
+  placeholders: &[&str],
+  script: &str,
+  source: &str,
+) -> Result<String> {
+  let grammars = match query {
+    Some(query) => common::grammars_with_markdown_query(query)?,
+    None => common::grammars()?,
+  };
+  let languages = HashMap::from([(
+    "sql".to_string(),
+    LanguageSpec::Table {
+      formatters: vec!["script".into()],
+      settings: LanguageSettings {
+        placeholders: placeholders
+          .iter()
+          .map(|pattern| pattern.to_string())
+          .collect(),
+        ..Default::default()
+      },
+    },
+  )]);
+  let formatters = HashMap::from([("script".to_string(), common::shell_formatter(script))]);
+
+  common::format_source(
+    source,
+    "markdown",
+    false,
+    &common::format_context(&grammars, &languages, &formatters),
+  )
+}
+
+// Rejects anything which isn't a plain identifier, and squeezes repeated spaces
+const STRICT: &str = r#"input=$(cat)
+case "$input" in
+  *'$'*|*'{'*|*':'*|*'?'*|*'`'*) echo "unexpected placeholder" >&2; exit 1 ;;
+esac
+printf '%s\n' "$input" | tr -s ' '"#;
+
+const PLACEHOLDERS: &[&str] = &[r"\$\{[^}]*\}", r"\{\{.*?\}\}", r":\w+", r"\?"];
+
+#[test]
+fn placeholder_patterns_are_masked_while_formatting() -> Result<()> {
+  let source =
+    "```sql\nselect  *  from ${table}\nwhere  id = :id and  name = {{ param }} and  x = ?\n```\n";
+  let result = format_markdown(None, PLACEHOLDERS, STRICT, source)?;
+  assert_eq!(
+    result,
+    "```sql\nselect * from ${table}\nwhere id = :id and name = {{ param }} and x = ?\n```\n"
+  );
+
+  Ok(())
+}
+
+// Templates aren't valid Rust until their placeholders are replaced
+#[test]
+fn templates_are_formatted_with_rustfmt() -> Result<()> {
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([(
+    "rust".to_string(),
+    LanguageSpec::Table {
+      formatters: vec!["rustfmt".into()],
+      settings: LanguageSettings {
+        placeholders: vec![r"\{\{.*?\}\}".into()],
+        ..Default::default()
+      },
+    },
+  )]);
+  let formatters = HashMap::from([("rustfmt".to_string(), common::rustfmt())]);
+
+  common::assert_fixture(
+    "placeholders",
+    &common::format_context(&grammars, &languages, &formatters),
+  )
+}
+
+#[test]
+fn placeholders_captured_by_the_query_are_masked() -> Result<()> {
+  let query = r#"((fenced_code_block
+  (fenced_code_block_delimiter) @pruner.placeholder
+  (code_fence_content)
+  (fenced_code_block_delimiter) @pruner.placeholder) @injection.content
+  (#set! injection.language "sql")
+  (#set! injection.include-children))"#;
+  let result = format_markdown(Some(query), &[], STRICT, "```\nselect   1\n```\n")?;
+  assert_eq!(result, "```\nselect 1\n```\n");
+
+  Ok(())
+}
+
+#[test]
+fn removing_a_placeholder_is_an_error() {
+  let err = format_markdown(
+    None,
+    PLACEHOLDERS,
+    "tr a-z A-Z",
+    "```sql\nselect * from t where id = :id\n```\n",
+  )
+  .expect_err("the removed placeholder should fail");
+  assert!(
+    format!("{err:#}").contains("removed the placeholder \":id\""),
+    "unexpected error: {err:#}"
+  );
+}
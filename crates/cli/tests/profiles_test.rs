@@ -1,4 +1,4 @@
-use pruner::config::{ConfigFile, LoadOpts, ProfileConfig};
+use pruner::config::{ConfigFile, LanguageSpec, LoadOpts, ProfileConfig};
 use std::{
   collections::HashMap,
   fs::{self, File},
@@ -25,8 +25,14 @@ fn apply_single_profile() {
     grammar_download_dir: Some(PathBuf::from("base_downloads")),
     grammar_build_dir: Some(PathBuf::from("base_build")),
     languages: Some(HashMap::from([
-      ("markdown".to_string(), vec!["prettier".into()]),
-      ("rust".to_string(), vec!["rustfmt".into()]),
+      (
+        "markdown".to_string(),
+        LanguageSpec::Formatters(vec!["prettier".into()]),
+      ),
+      (
+        "rust".to_string(),
+        LanguageSpec::Formatters(vec!["rustfmt".into()]),
+      ),
     ])),
     ..Default::default()
   };
@@ -36,7 +42,7 @@ fn apply_single_profile() {
     grammar_download_dir: Some(PathBuf::from("profile_downloads")),
     languages: Some(HashMap::from([(
       "markdown".to_string(),
-      vec!["profile_prettier".into()],
+      LanguageSpec::Formatters(vec!["profile_prettier".into()]),
     )])),
     ..Default::default()
   };
@@ -65,10 +71,13 @@ fn apply_single_profile() {
 
   let languages = result.languages.unwrap();
   assert_eq!(
-    languages.get("markdown").unwrap(),
+    languages.get("markdown").unwrap().formatters(),
     &vec!["profile_prettier".into()]
   );
-  assert_eq!(languages.get("rust").unwrap(), &vec!["rustfmt".into()]);
+  assert_eq!(
+    languages.get("rust").unwrap().formatters(),
+    &vec!["rustfmt".into()]
+  );
 }
 
 #[test]
@@ -77,9 +86,18 @@ fn apply_multiple_profiles_in_order() {
     query_paths: Some(vec![PathBuf::from("base")]),
     grammar_download_dir: Some(PathBuf::from("base_downloads")),
     languages: Some(HashMap::from([
-      ("markdown".to_string(), vec!["base_md".into()]),
-      ("rust".to_string(), vec!["base_rust".into()]),
-      ("python".to_string(), vec!["base_python".into()]),
+      (
+        "markdown".to_string(),
+        LanguageSpec::Formatters(vec!["base_md".into()]),
+      ),
+      (
+        "rust".to_string(),
+        LanguageSpec::Formatters(vec!["base_rust".into()]),
+      ),
+      (
+        "python".to_string(),
+        LanguageSpec::Formatters(vec!["base_python".into()]),
+      ),
     ])),
     ..Default::default()
   };
@@ -88,8 +106,14 @@ fn apply_multiple_profiles_in_order() {
     query_paths: Some(vec![PathBuf::from("profile_a")]),
     grammar_download_dir: Some(PathBuf::from("profile_a_downloads")),
     languages: Some(HashMap::from([
-      ("markdown".to_string(), vec!["profile_a_md".into()]),
-      ("rust".to_string(), vec!["profile_a_rust".into()]),
+      (
+        "markdown".to_string(),
+        LanguageSpec::Formatters(vec!["profile_a_md".into()]),
+      ),
+      (
+        "rust".to_string(),
+        LanguageSpec::Formatters(vec!["profile_a_rust".into()]),
+      ),
     ])),
     ..Default::default()
   };
@@ -99,7 +123,7 @@ fn apply_multiple_profiles_in_order() {
     grammar_build_dir: Some(PathBuf::from("profile_b_build")),
     languages: Some(HashMap::from([(
       "markdown".to_string(),
-      vec!["profile_b_md".into()],
+      LanguageSpec::Formatters(vec!["profile_b_md".into()]),
     )])),
     ..Default::default()
   };
@@ -125,17 +149,17 @@ fn apply_multiple_profiles_in_order() {
 
   let languages = result.languages.unwrap();
   assert_eq!(
-    languages.get("markdown").unwrap(),
+    languages.get("markdown").unwrap().formatters(),
     &vec!["profile_b_md".into()],
     "profile_b should override profile_a's markdown setting"
   );
   assert_eq!(
-    languages.get("rust").unwrap(),
+    languages.get("rust").unwrap().formatters(),
     &vec!["profile_a_rust".into()],
     "profile_a's rust setting should persist since profile_b doesn't override it"
   );
   assert_eq!(
-    languages.get("python").unwrap(),
+    languages.get("python").unwrap().formatters(),
     &vec!["base_python".into()],
     "base python setting should persist since no profile overrides it"
   );
@@ -150,7 +174,7 @@ fn profile_with_empty_fields_does_not_override() {
     grammar_build_dir: Some(PathBuf::from("base_build")),
     languages: Some(HashMap::from([(
       "markdown".to_string(),
-      vec!["prettier".into()],
+      LanguageSpec::Formatters(vec!["prettier".into()]),
     )])),
     ..Default::default()
   };
@@ -176,7 +200,12 @@ fn profile_with_empty_fields_does_not_override() {
     PathBuf::from("base_build")
   );
   assert_eq!(
-    result.languages.unwrap().get("markdown").unwrap(),
+    result
+      .languages
+      .unwrap()
+      .get("markdown")
+      .unwrap()
+      .formatters(),
     &vec!["prettier".into()]
   );
 }
@@ -219,11 +248,11 @@ markdown = ["ci_prettier"]
   );
 
   assert_eq!(
-    config.languages.get("markdown").unwrap(),
+    config.languages.get("markdown").unwrap().formatters(),
     &vec!["ci_prettier".into()]
   );
   assert_eq!(
-    config.languages.get("rust").unwrap(),
+    config.languages.get("rust").unwrap().formatters(),
     &vec!["rustfmt".into()]
   );
 }
@@ -278,17 +307,17 @@ markdown = ["debug_prettier"]
   );
 
   assert_eq!(
-    config.languages.get("markdown").unwrap(),
+    config.languages.get("markdown").unwrap().formatters(),
     &vec!["debug_prettier".into()],
     "debug profile should override ci profile's markdown"
   );
   assert_eq!(
-    config.languages.get("rust").unwrap(),
+    config.languages.get("rust").unwrap().formatters(),
     &vec!["ci_rustfmt".into()],
     "ci profile's rust should persist since debug doesn't override it"
   );
   assert_eq!(
-    config.languages.get("python").unwrap(),
+    config.languages.get("python").unwrap().formatters(),
     &vec!["black".into()],
     "base python should persist since no profile overrides it"
   );
@@ -0,0 +1,101 @@
+use anyhow::Result;
+use std::{
+  fs,
+  ops::Deref,
+  path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
+};
+use tree_sitter::Query;
+
+use pruner::api::queries;
+
+mod common;
+
+fn unique_temp_dir() -> PathBuf {
+  let nanos = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .expect("time should be available")
+    .as_nanos();
+  let temp_dir = std::env::temp_dir().join(format!("pruner-queries-test-{nanos}"));
+  fs::create_dir_all(&temp_dir).expect("should create temp dir");
+  temp_dir
+}
+
+fn write_query(dir: &Path, lang: &str, contents: &str) {
+  fs::create_dir_all(dir.join(lang)).expect("should create query dir");
+  fs::write(dir.join(lang).join("injections.scm"), contents).expect("should write query");
+}
+
+fn injection_languages(query: &Query) -> Vec<String> {
+  (0..query.pattern_count())
+    .flat_map(|pattern| query.property_settings(pattern))
+    .filter(|property| property.key.deref() == "injection.language")
+    .filter_map(|property| property.value.as_deref().map(String::from))
+    .collect()
+}
+
+#[test]
+fn resolves_inherited_queries() -> Result<()> {
+  let grammars = common::grammars()?;
+  let markdown = grammars
+    .get("markdown")
+    .ok_or_else(|| anyhow::anyhow!("Missing markdown grammar"))?;
+
+  let dir = unique_temp_dir();
+  write_query(
+    &dir,
+    "markdown",
+    "; inherits: base,(optional)\n((inline) @injection.content (#set! injection.language \"own\"))\n",
+  );
+  write_query(
+    &dir,
+    "base",
+    "; inherits: markdown\n((inline) @injection.content (#set! injection.language \"base\"))\n",
+  );
+  write_query(
+    &dir,
+    "optional",
+    "((inline) @injection.content (#set! injection.language \"optional\"))\n",
+  );
+
+  let query =
+    queries::load_injections_query(&markdown.lang, "markdown", &[], std::slice::from_ref(&dir))?;
+  assert_eq!(injection_languages(&query), vec!["base", "optional", "own"]);
+
+  fs::remove_dir_all(dir)?;
+  Ok(())
+}
+
+#[test]
+fn extends_with_a_relaxed_modeline() -> Result<()> {
+  let grammars = common::grammars()?;
+  let markdown = grammars
+    .get("markdown")
+    .ok_or_else(|| anyhow::anyhow!("Missing markdown grammar"))?;
+
+  let base_dir = unique_temp_dir();
+  let base = base_dir.join("base.scm");
+  fs::write(
+    &base,
+    "((inline) @injection.content (#set! injection.language \"base\"))\n",
+  )?;
+
+  let dir = unique_temp_dir();
+  write_query(
+    &dir,
+    "markdown",
+    "; extends\n((inline) @injection.content (#set! injection.language \"extended\"))\n",
+  );
+
+  let query = queries::load_injections_query(
+    &markdown.lang,
+    "markdown",
+    &[base],
+    std::slice::from_ref(&dir),
+  )?;
+  assert_eq!(injection_languages(&query), vec!["base", "extended"]);
+
+  fs::remove_dir_all(dir)?;
+  fs::remove_dir_all(base_dir)?;
+  Ok(())
+}
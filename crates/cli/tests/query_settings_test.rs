@@ -0,0 +1,224 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{api::format::SINGLE_LINE_PRINT_WIDTH, config::LanguageSpec};
+
+mod common;
+
+fn format_with_query(query: &str, source: &str) -> Result<String> {
+  let grammars = common::grammars_with_markdown_query(query)?;
+  let languages = HashMap::from([(
+    "sql".to_string(),
+    LanguageSpec::Formatters(vec!["upper".into()]),
+  )]);
+  let formatters = HashMap::from([
+    ("upper".to_string(), common::shell_formatter("tr a-z A-Z")),
+    ("lower".to_string(), common::shell_formatter("tr A-Z a-z")),
+    (
+      "multiline".to_string(),
+      common::shell_formatter(
+        "cat > /dev/null; printf 'SELECT a,\n  b\n\nFROM t LIMIT %s\n' $textwidth",
+      ),
+    ),
+    (
+      "two-lines".to_string(),
+      common::shell_formatter("cat > /dev/null; printf 'A\\nB\\n'"),
+    ),
+    (
+      "selected".to_string(),
+      common::shell_formatter("sed s/SELECT/SELECTED/"),
+    ),
+    (
+      "heading".to_string(),
+      common::shell_formatter("cat > /dev/null; printf '# A\\nB\\n'"),
+    ),
+    (
+      "show-args".to_string(),
+      common::shell_formatter("cat > /dev/null; echo '$dialect $textwidth'"),
+    ),
+  ]);
+
+  common::format_source(
+    source,
+    "markdown",
+    false,
+    &common::format_context(&grammars, &languages, &formatters),
+  )
+}
+
+#[test]
+fn formatter_can_be_set_by_the_query() -> Result<()> {
+  let result = format_with_query(
+    r#"(fenced_code_block
+  (info_string (language) @injection.language)
+  (code_fence_content) @injection.content
+  (#set! pruner.formatter "lower"))"#,
+    "```sql\nSELECT 1\n```\n",
+  )?;
+  assert_eq!(result, "```sql\nselect 1\n```\n");
+
+  let err = format_with_query(
+    r#"(fenced_code_block
+  (info_string (language) @injection.language)
+  (code_fence_content) @injection.content
+  (#set! pruner.formatter "missing"))"#,
+    "```sql\nSELECT 1\n```\n",
+  )
+  .expect_err("an unknown formatter should fail");
+  assert!(format!("{err:#}").contains("Unknown formatter missing"));
+
+  Ok(())
+}
+
+#[test]
+fn regions_can_be_skipped_by_the_query() -> Result<()> {
+  let source = "```sql\nselect 1\n```\n";
+  let result = format_with_query(
+    r#"(fenced_code_block
+  (info_string (language) @injection.language)
+  (code_fence_content) @injection.content
+  (#set! pruner.skip true))"#,
+    source,
+  )?;
+  assert_eq!(result, source);
+
+  Ok(())
+}
+
+#[test]
+fn captures_and_print_width_reach_formatter_arguments() -> Result<()> {
+  let query = r#"(fenced_code_block
+  (info_string (language) @injection.language) @pruner.dialect
+  (code_fence_content) @injection.content
+  (#gsub! @pruner.dialect "^%S+%s+dialect=" "")
+  (#set! pruner.formatter "show-args")
+  (#set! pruner.print-width 60))"#;
+
+  let result = format_with_query(
+    query,
+    "```sql dialect=postgres\nselect 1\n```\n\n```sql dialect=mysql\nselect 1\n```\n",
+  )?;
+  assert_eq!(
+    result,
+    "```sql dialect=postgres\npostgres 60\n```\n\n```sql dialect=mysql\nmysql 60\n```\n"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn print_width_can_be_set_by_the_query() -> Result<()> {
+  let grammars = common::fixture_grammars("query_print_width")?;
+  let languages = HashMap::from([(
+    "rust".to_string(),
+    LanguageSpec::Formatters(vec!["rustfmt".into()]),
+  )]);
+  let formatters = HashMap::from([("rustfmt".to_string(), common::rustfmt())]);
+
+  common::assert_fixture(
+    "query_print_width",
+    &common::format_context(&grammars, &languages, &formatters),
+  )
+}
+
+#[test]
+fn single_line_regions_are_joined_onto_one_line() -> Result<()> {
+  let result = format_with_query(
+    r#"(fenced_code_block
+  (info_string (language) @injection.language)
+  (code_fence_content) @injection.content
+  (#set! pruner.formatter "multiline")
+  (#set! pruner.single-line true))"#,
+    "```sql\nselect a, b from t\n```\n",
+  )?;
+  assert_eq!(
+    result,
+    format!("```sql\nSELECT a, b FROM t LIMIT {SINGLE_LINE_PRINT_WIDTH}\n```\n")
+  );
+
+  Ok(())
+}
+
+#[test]
+fn single_line_regions_are_left_as_they_are_when_joining_changes_them() -> Result<()> {
+  let query = |formatter: &str| {
+    format!(
+      r#"(fenced_code_block
+  (code_fence_content) @injection.content
+  (#set! injection.language "markdown")
+  (#set! pruner.formatter "{formatter}")
+  (#set! pruner.single-line true))
+((inline) @injection.content
+  (#set! injection.language "markdown_inline"))"#
+    )
+  };
+
+  // Joining the lines of a paragraph keeps it a paragraph
+  let result = format_with_query(&query("two-lines"), "```\na\n```\n")?;
+  assert_eq!(result, "```\nA B\n```\n");
+
+  // Joining a heading onto the line after it would take that line into the heading
+  let result = format_with_query(&query("heading"), "```\na\n```\n")?;
+  assert_eq!(result, "```\na\n```\n");
+
+  Ok(())
+}
+
+#[test]
+fn nested_regions_are_formatted_before_their_parent() -> Result<()> {
+  let result = format_with_query(
+    r#"((code_fence_content) @injection.content (#set! injection.language "sql"))
+((fenced_code_block) @injection.content
+  (#set! injection.language "text")
+  (#set! injection.include-children)
+  (#set! pruner.formatter "selected"))"#,
+    "```sql\nselect 1\n```\n",
+  )?;
+  assert_eq!(result, "```sql\nSELECTED 1\n```\n");
+
+  Ok(())
+}
+
+#[test]
+fn newlines_around_regions_can_be_set_by_the_query() -> Result<()> {
+  let result = format_with_query(
+    r#"(fenced_code_block
+  (info_string (language) @injection.language)
+  (code_fence_content) @injection.content
+  (#set! pruner.leading-newline true)
+  (#set! pruner.trailing-newline 2))"#,
+    "```sql\nselect 1\n```\n",
+  )?;
+  assert_eq!(result, "```sql\n\nSELECT 1\n\n```\n");
+
+  Ok(())
+}
+
+#[test]
+fn indentation_of_regions_can_be_set_by_the_query() -> Result<()> {
+  let block = format_with_query(
+    r#"(fenced_code_block
+  (info_string (language) @injection.language)
+  (code_fence_content) @injection.content
+  (#set! pruner.indent 2))"#,
+    "```sql\nselect 1\nfrom t\n```\n",
+  )?;
+  assert_eq!(block, "```sql\n  SELECT 1\n  FROM T\n```\n");
+
+  // Regions starting mid-line are aligned with their first line, or given a hanging indent
+  let info_string = |indent: &str| {
+    format_with_query(
+      &format!(
+        r#"((language) @injection.content
+  (#set! injection.language "sql")
+  (#set! pruner.formatter "two-lines")
+  (#set! pruner.indent "{indent}"))"#
+      ),
+      "```sql\nselect 1\n```\n",
+    )
+  };
+  assert_eq!(info_string("align")?, "```A\n   B\nselect 1\n```\n");
+  assert_eq!(info_string("2")?, "```A\n  B\nselect 1\n```\n");
+
+  Ok(())
+}
@@ -0,0 +1,140 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::format::FormatContext,
+  config::{LanguageSpec, SafetyCheck},
+};
+
+mod common;
+
+const SOURCE: &str = "# Title\n\n```sql\nselect 1\n```\n\nText\n";
+
+fn format_checked(script: &str, safety_check: SafetyCheck) -> Result<(String, Vec<String>)> {
+  format_source_checked(SOURCE, script, safety_check)
+}
+
+// Formats the sql and markdown regions of the source with the script, leaving its root alone
+fn format_source_checked(
+  source: &str,
+  script: &str,
+  safety_check: SafetyCheck,
+) -> Result<(String, Vec<String>)> {
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([
+    (
+      "sql".to_string(),
+      LanguageSpec::Formatters(vec!["script".into()]),
+    ),
+    (
+      "markdown".to_string(),
+      LanguageSpec::Formatters(vec!["script".into()]),
+    ),
+  ]);
+  let formatters = HashMap::from([("script".to_string(), common::shell_formatter(script))]);
+  let context = FormatContext {
+    safety_check,
+    ..common::format_context(&grammars, &languages, &formatters)
+  };
+
+  let result = common::format_source(source, "markdown", false, &context)?;
+
+  let warnings = context
+    .warnings
+    .take()
+    .iter()
+    .map(|warning| warning.to_string())
+    .collect();
+  Ok((result, warnings))
+}
+
+// Closes the code fence early, turning the rest of the region into markdown.
+const BREAK_FENCE: &str = "cat; printf '```\\n# Heading\\n'";
+
+// Adds a NUL byte, which markdown parses as a syntax error.
+const ADD_ERROR: &str = "cat; printf '\\000\\n'";
+
+#[test]
+fn safe_output_is_accepted() -> Result<()> {
+  let (result, warnings) = format_checked("tr a-z A-Z", SafetyCheck::Strict)?;
+  assert_eq!(result, "# Title\n\n```sql\nSELECT 1\n```\n\nText\n");
+  assert!(warnings.is_empty(), "{warnings:?}");
+  Ok(())
+}
+
+#[test]
+fn output_changing_the_host_tree_is_rejected() -> Result<()> {
+  let (result, warnings) = format_checked(BREAK_FENCE, SafetyCheck::Strict)?;
+  assert_eq!(result, SOURCE);
+  assert_eq!(
+    warnings,
+    vec![
+      "[stdin]:4 [sql] Rejected the formatted region, in [markdown] the syntax tree of the \
+       result differs from the input"
+    ]
+  );
+
+  let (result, _) = format_checked(BREAK_FENCE, SafetyCheck::Off)?;
+  assert_eq!(
+    result,
+    "# Title\n\n```sql\nselect 1\n```\n# Heading\n```\n\nText\n"
+  );
+
+  Ok(())
+}
+
+#[test]
+fn syntax_check_only_rejects_new_errors() -> Result<()> {
+  let (result, warnings) = format_checked(BREAK_FENCE, SafetyCheck::Syntax)?;
+  assert_eq!(
+    result,
+    "# Title\n\n```sql\nselect 1\n```\n# Heading\n```\n\nText\n"
+  );
+  assert!(warnings.is_empty(), "{warnings:?}");
+
+  let (result, warnings) = format_checked("cat; printf '```\\n\\000\\n'", SafetyCheck::Syntax)?;
+  assert_eq!(result, SOURCE);
+  assert_eq!(
+    warnings,
+    vec![
+      "[stdin]:4 [sql] Rejected the formatted region, in [markdown] the result has 2 syntax \
+       errors where the input had 0"
+    ]
+  );
+
+  Ok(())
+}
+
+#[test]
+fn regions_are_checked_with_their_own_grammar() -> Result<()> {
+  let source = "# Title\n\n```markdown\nText\n```\n";
+  let (result, warnings) = format_source_checked(source, ADD_ERROR, SafetyCheck::Syntax)?;
+  assert_eq!(result, source);
+  assert_eq!(
+    warnings,
+    vec![
+      "[stdin]:4 [markdown] Rejected the output of the [markdown] formatters, the result has 2 \
+       syntax errors where the input had 0"
+    ]
+  );
+
+  Ok(())
+}
+
+#[test]
+fn only_the_regions_breaking_the_host_are_rejected() -> Result<()> {
+  let source = common::load_file("safety_check/input.md");
+  let script = "input=$(cat); printf '%s\\n' \"$input\" | tr a-z A-Z; case \"$input\" in *bad*) \
+                printf '```\\n# Heading\\n';; esac";
+  let (result, warnings) = format_source_checked(&source, script, SafetyCheck::Strict)?;
+  assert_eq!(result, common::load_file("safety_check/output.md"));
+  assert_eq!(
+    warnings,
+    [8, 16].map(|line| format!(
+      "[stdin]:{line} [sql] Rejected the formatted region, in [markdown] the syntax tree of the \
+       result differs from the input"
+    ))
+  );
+
+  Ok(())
+}
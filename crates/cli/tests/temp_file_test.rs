@@ -0,0 +1,144 @@
+use anyhow::Result;
+use std::{
+  collections::HashMap,
+  fs,
+  path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use pruner::{
+  api::format::{self, FormatOpts},
+  config::{FormatterSpec, LanguageSpec, TempFileLocation, TempFileSpec},
+};
+
+mod common;
+
+fn create_temp_dir(prefix: &str) -> Result<PathBuf> {
+  let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+  let dir = std::env::temp_dir().join(format!("{prefix}-{}-{nanos}", std::process::id()));
+  fs::create_dir_all(&dir)?;
+  Ok(dir)
+}
+
+/// Formats a snippet with a formatter that replaces the temp file contents with the temp file's own
+/// path, returning the path that the formatter was called with.
+fn format_with_temp_file(temp_file: TempFileSpec, path: &Path) -> Result<PathBuf> {
+  let grammars = HashMap::new();
+  let languages = HashMap::from([(
+    "sql".to_string(),
+    LanguageSpec::Formatters(vec!["echo-path".into()]),
+  )]);
+  let formatters = HashMap::from([(
+    "echo-path".to_string(),
+    FormatterSpec {
+      stdin: Some(false),
+      temp_file: Some(temp_file),
+      ..common::shell_formatter("printf '%s' '$file' > '$file'")
+    },
+  )]);
+
+  let result = format::format(
+    b"select 1",
+    &FormatOpts {
+      printwidth: 80,
+      language: "sql",
+      path: Some(path),
+      indent_width: None,
+      variables: &[],
+    },
+    true,
+    true,
+    &common::format_context(&grammars, &languages, &formatters),
+  )?;
+
+  Ok(PathBuf::from(String::from_utf8(result)?))
+}
+
+#[test]
+fn temp_file_adjacent_to_original() -> Result<()> {
+  let dir = create_temp_dir("pruner-temp-file-adjacent")?;
+  let path = dir.join("query.sql");
+
+  let temp_path = format_with_temp_file(TempFileSpec::Location(TempFileLocation::Adjacent), &path)?;
+
+  assert_eq!(temp_path.parent(), Some(dir.as_path()));
+  let name = temp_path.file_name().unwrap().to_string_lossy();
+  assert!(name.starts_with(".query.pruner-"), "{name}");
+  assert!(name.ends_with(".sql"), "{name}");
+  assert!(!temp_path.exists(), "temp file should be removed");
+
+  let _ = fs::remove_dir_all(&dir);
+  Ok(())
+}
+
+#[test]
+fn temp_file_in_sandbox_dir() -> Result<()> {
+  let dir = create_temp_dir("pruner-temp-file-sandbox")?;
+  let sandbox = dir.join("sandbox");
+  let path = dir.join("nested").join("query.sql");
+
+  let temp_path = format_with_temp_file(
+    TempFileSpec::Sandbox {
+      dir: sandbox.clone(),
+    },
+    &path,
+  )?;
+
+  assert_eq!(temp_path.parent(), Some(sandbox.as_path()));
+  let name = temp_path.file_name().unwrap().to_string_lossy();
+  assert!(name.starts_with(".query.pruner-"), "{name}");
+  assert!(name.ends_with(".sql"), "{name}");
+
+  let _ = fs::remove_dir_all(&dir);
+  Ok(())
+}
+
+#[test]
+fn injected_regions_are_named_after_their_grammar_file_type() -> Result<()> {
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([
+    (
+      "markdown".to_string(),
+      LanguageSpec::Formatters(vec!["echo-name".into()]),
+    ),
+    (
+      "sql".to_string(),
+      LanguageSpec::Formatters(vec!["echo-name".into()]),
+    ),
+  ]);
+  let formatters = HashMap::from([(
+    "echo-name".to_string(),
+    FormatterSpec {
+      stdin: Some(false),
+      ..common::shell_formatter("basename '$file' > '$file'")
+    },
+  )]);
+
+  let result = format::format(
+    b"```markdown\na\n```\n\n```sql\nselect 1\n```\n",
+    &FormatOpts {
+      printwidth: 80,
+      language: "markdown",
+      path: Some(Path::new("/repo/notes.txt")),
+      indent_width: None,
+      variables: &[],
+    },
+    false,
+    true,
+    &common::format_context(&grammars, &languages, &formatters),
+  )?;
+
+  let names = String::from_utf8(result)?
+    .lines()
+    .filter(|line| line.starts_with(".notes."))
+    .map(|name| {
+      name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_string())
+    })
+    .collect::<Vec<_>>();
+  // Markdown takes the file type of its grammar, sql has no grammar and falls back to its name
+  assert_eq!(names, vec![Some("md".to_string()), Some("sql".to_string())]);
+
+  Ok(())
+}
@@ -0,0 +1,70 @@
+use pruner::api::text;
+
+#[test]
+fn indent_for_byte_keeps_tabs() {
+  let source = b"(ns foo)\n\t(def x \"doc\")";
+  let index = source.len() - 5;
+
+  assert_eq!(text::indent_for_byte(source, index), "\t        ");
+  assert_eq!(text::column_for_byte(source, index, 4), 12);
+  assert_eq!(text::column_for_byte(source, index, 8), 16);
+}
+
+#[test]
+fn display_width_expands_tabs_to_tab_stops() {
+  assert_eq!(text::display_width("\t", 4), 4);
+  assert_eq!(text::display_width("  \t", 4), 4);
+  assert_eq!(text::display_width("\t  \t", 4), 8);
+  assert_eq!(text::display_width("\t", 8), 8);
+}
+
+#[test]
+fn min_leading_indent_is_shared_prefix() {
+  assert_eq!(text::min_leading_indent("    a\n      b\n"), "    ");
+  assert_eq!(text::min_leading_indent("\t\ta\n\n\tb\n"), "\t");
+  assert_eq!(text::min_leading_indent("\t  a\n\t b\n"), "\t ");
+  assert_eq!(text::min_leading_indent("  a\n\tb\n"), "");
+}
+
+#[test]
+fn strip_leading_indent_with_tabs() {
+  assert_eq!(
+    text::strip_leading_indent("a\n\t\tb\n\tc\n", "\t", 4),
+    "a\n\tb\nc\n"
+  );
+  assert_eq!(
+    text::strip_leading_indent("a\n\t  b\n\t    c\n", "\t  ", 4),
+    "a\nb\n  c\n"
+  );
+}
+
+#[test]
+fn strip_leading_indent_with_mixed_indentation() {
+  // Lines indented with spaces are stripped up to the width of a tab indent.
+  assert_eq!(
+    text::strip_leading_indent("a\n      b\n  c\n", "\t", 4),
+    "a\n  b\nc\n"
+  );
+  // A tab which is wider than the remaining indent is left in place.
+  assert_eq!(
+    text::strip_leading_indent("a\n  \tb\n", "   ", 4),
+    "a\n\tb\n"
+  );
+}
+
+#[test]
+fn offset_lines_uses_indent_characters() {
+  let mut data = b"a\nb\n\nc\n".to_vec();
+  text::offset_lines(&mut data, "\t ");
+  assert_eq!(data, b"a\n\t b\n\n\t c\n");
+}
+
+#[test]
+fn line_prefixes_are_stripped_and_restored() {
+  let stripped = text::strip_line_prefix("/// a\n///\n///   b\nc", "/// ");
+  assert_eq!(stripped, "a\n\n  b\nc");
+  assert_eq!(
+    text::add_line_prefix(&stripped, "/// "),
+    "/// a\n///\n///   b\n/// c"
+  );
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::{
+  api::{format::FormatContext, grammar::Grammars},
+  config::{FormatterSpec, FormatterSpecs, LanguageFormatters, LanguageSpec},
+};
+
+mod common;
+
+fn verified_context<'a>(
+  grammars: &'a Grammars,
+  languages: &'a LanguageFormatters,
+  formatters: &'a FormatterSpecs,
+) -> FormatContext<'a> {
+  FormatContext {
+    verify_idempotent: true,
+    ..common::format_context(grammars, languages, formatters)
+  }
+}
+
+fn formatters(formatter: FormatterSpec) -> FormatterSpecs {
+  HashMap::from([("script".to_string(), formatter)])
+}
+
+#[test]
+fn idempotent_formatters_pass() -> Result<()> {
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([(
+    "json".to_string(),
+    LanguageSpec::Formatters(vec!["script".into()]),
+  )]);
+  let formatters = formatters(common::jq());
+
+  common::assert_fixture(
+    "verify_idempotent",
+    &verified_context(&grammars, &languages, &formatters),
+  )
+}
+
+#[test]
+fn non_idempotent_formatters_fail_with_a_diff() -> Result<()> {
+  let grammars = common::grammars()?;
+  let languages = HashMap::from([(
+    "sql".to_string(),
+    LanguageSpec::Formatters(vec!["script".into()]),
+  )]);
+  let formatters = formatters(common::shell_formatter("sed 's/^/-- /'"));
+
+  let err = common::format_source(
+    "# Title\n\n```sql\nselect 1\n```\n",
+    "markdown",
+    true,
+    &verified_context(&grammars, &languages, &formatters),
+  )
+  .expect_err("formatting should not converge");
+  assert_eq!(
+    err.to_string(),
+    "Formatting is not idempotent, the [sql] region at line 4 changed when formatted again \
+     (formatters: script)\n@@ line 4 @@\n--- select 1\n+-- -- select 1\n"
+  );
+
+  Ok(())
+}
@@ -0,0 +1,82 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use pruner::config::LanguageSpec;
+
+mod common;
+
+fn format_wrapped(prefix: &str, suffix: &str, script: &str, source: &str) -> Result<String> {
+  let grammars = common::grammars_with_markdown_query(&format!(
+    r#"((code_fence_content) @injection.content
+  (#set! injection.language "js")
+  (#set! pruner.wrap-prefix "{prefix}")
+  (#set! pruner.wrap-suffix "{suffix}"))"#
+  ))?;
+  let languages = HashMap::from([(
+    "js".to_string(),
+    LanguageSpec::Formatters(vec!["script".into()]),
+  )]);
+  let formatters = HashMap::from([("script".to_string(), common::shell_formatter(script))]);
+
+  common::format_source(
+    source,
+    "markdown",
+    false,
+    &common::format_context(&grammars, &languages, &formatters),
+  )
+}
+
+// Only accepts whole statements, and squeezes repeated spaces
+const STATEMENTS: &str = r#"input=$(cat)
+case "$input" in
+  "const _ = "*";") ;;
+  *) echo "not a statement" >&2; exit 1 ;;
+esac
+printf '%s\n' "$input" | tr -s ' '"#;
+
+#[test]
+fn fragments_are_wrapped_while_formatting() -> Result<()> {
+  let result = format_wrapped("const _ = ", ";", STATEMENTS, "```\na  +   b\n```\n")?;
+  assert_eq!(result, "```\na + b\n```\n");
+
+  Ok(())
+}
+
+#[test]
+fn indentation_added_within_the_wrapper_is_removed() -> Result<()> {
+  let result = format_wrapped(
+    "(",
+    ")",
+    r#"input=$(cat); body=${input#(}; body=${body%)}; printf '(\n    %s\n)\n' "$body""#,
+    "```\na + b\n```\n",
+  )?;
+  assert_eq!(result, "```\na + b\n```\n");
+
+  Ok(())
+}
+
+// rustfmt only formats whole items, and indents the statements of the function body
+#[test]
+fn statements_are_formatted_within_a_function() -> Result<()> {
+  let grammars = common::fixture_grammars("wrap")?;
+  let languages = HashMap::from([(
+    "rust".to_string(),
+    LanguageSpec::Formatters(vec!["rustfmt".into()]),
+  )]);
+  let formatters = HashMap::from([("rustfmt".to_string(), common::rustfmt())]);
+
+  common::assert_fixture(
+    "wrap",
+    &common::format_context(&grammars, &languages, &formatters),
+  )
+}
+
+#[test]
+fn changing_the_wrapper_is_an_error() {
+  let err = format_wrapped("const _ = ", ";", "sed 's/const/let/'", "```\na + b\n```\n")
+    .expect_err("the changed wrapper should fail");
+  assert!(
+    format!("{err:#}").contains("changed the wrap prefix"),
+    "unexpected error: {err:#}"
+  );
+}